@@ -0,0 +1,115 @@
+//! Admin endpoints, only mounted when an admin token is configured.
+//!
+//! - `GET /admin/log-level` returns the current and startup log filter directives,
+//! - `PUT /admin/log-level` with `{"directives": "auth_webhook=debug"}` replaces them,
+//! - `DELETE /admin/log-level` restores the startup directives.
+//!
+//! Requests must carry the admin token as `Authorization: Bearer <token>`.
+
+use std::sync::Arc;
+
+use axum::{extract::State, http::HeaderMap, response::Json, routing::get, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+use tracing_ext::LogLevelHandle;
+
+use crate::errors::{SrvError, SrvErrorKind};
+
+#[derive(Debug)]
+struct AdminState {
+    token: String,
+    log_level: LogLevelHandle,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogLevelUpdate {
+    directives: String,
+}
+
+/// The admin routes, guarded by `token`.
+pub fn router(token: String, log_level: LogLevelHandle) -> Router {
+    Router::new()
+        .route(
+            "/admin/log-level",
+            get(get_log_level)
+                .put(set_log_level)
+                .delete(reset_log_level),
+        )
+        .with_state(Arc::new(AdminState { token, log_level }))
+}
+
+async fn get_log_level(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, SrvError> {
+    authorize(&state.token, &headers)?;
+    log_level_response(&state.log_level)
+}
+
+async fn set_log_level(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    Json(update): Json<LogLevelUpdate>,
+) -> Result<Json<Value>, SrvError> {
+    authorize(&state.token, &headers)?;
+    state
+        .log_level
+        .set_directives(&update.directives)
+        .map_err(|error| SrvErrorKind::BadRequest(error.to_string()))?;
+    info!(directives = update.directives, "log level changed");
+    log_level_response(&state.log_level)
+}
+
+async fn reset_log_level(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, SrvError> {
+    authorize(&state.token, &headers)?;
+    state.log_level.reset().map_err(anyhow::Error::new)?;
+    info!("log level reset");
+    log_level_response(&state.log_level)
+}
+
+fn log_level_response(log_level: &LogLevelHandle) -> Result<Json<Value>, SrvError> {
+    let directives = log_level.directives().map_err(anyhow::Error::new)?;
+    Ok(Json(json!({
+        "directives": directives,
+        "initial_directives": log_level.initial_directives(),
+    })))
+}
+
+fn authorize(admin_token: &str, headers: &HeaderMap) -> Result<(), SrvError> {
+    let token = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if token.is_empty() || !constant_time_eq(token.as_bytes(), admin_token.as_bytes()) {
+        return Err(SrvErrorKind::Unauthorized("invalid admin token".into()).into());
+    }
+    Ok(())
+}
+
+/// Compares without returning early on the first difference, so that the time taken does not leak
+/// how much of the token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let mut headers = HeaderMap::new();
+        assert!(authorize("s3cret", &headers).is_err());
+
+        headers.insert("authorization", "Bearer s3cre".parse().unwrap());
+        assert!(authorize("s3cret", &headers).is_err());
+
+        headers.insert("authorization", "Bearer s3cret".parse().unwrap());
+        assert!(authorize("s3cret", &headers).is_ok());
+    }
+}
@@ -1,9 +1,15 @@
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
 use axum::{extract::Query, http::StatusCode, response::Json};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use tracing::debug;
+use tracing_ext::{
+    add_span_event_on_active_span, global_tracer, record_exception_on_active_span, traced,
+    AttributeVisibility, HeaderRedactor, Secret, SpanEvent, SpanLink, SpanVisibility,
+    TracedHttpClient,
+};
 
 use crate::errors::{SrvError, SrvErrorKind};
 use crate::validator::ApiKeyQuery;
@@ -22,8 +28,27 @@ impl Consumer {
     }
 }
 
+/// Masks the credentials in the logged headers, set once at startup.
+static HEADER_REDACTOR: OnceLock<HeaderRedactor> = OnceLock::new();
+
+/// Sets the headers masked in the logs in addition to the default sensitive ones.
+pub fn init_header_redactor(redactor: HeaderRedactor) {
+    let _ = HEADER_REDACTOR.set(redactor);
+}
+
+fn header_redactor() -> &'static HeaderRedactor {
+    HEADER_REDACTOR.get_or_init(HeaderRedactor::default)
+}
+
+/// The client used for the requests to Kong, shared to reuse its connection pool.
+pub fn http_client() -> &'static TracedHttpClient {
+    static HTTP_CLIENT: OnceLock<TracedHttpClient> = OnceLock::new();
+    HTTP_CLIENT.get_or_init(|| TracedHttpClient::new(reqwest::Client::new()))
+}
+
+#[traced(name = "get_profile", visibility = "internal")]
 #[tracing::instrument]
-async fn get_profile(api_key: String) -> Result<Value, SrvError> {
+async fn get_profile(api_key: Secret<String>) -> Result<Value, SrvError> {
     let base_url = std::env::var("KONG_URL").map_err(|_| {
         SrvErrorKind::Custom(
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -31,9 +56,26 @@ async fn get_profile(api_key: String) -> Result<Value, SrvError> {
         )
     })?;
 
-    let url = format!("{}/key-auths/{}/consumer", base_url, api_key);
-    debug!("Fetching consumer from: {}", url);
-    let consumer = reqwest::get(&url).await?.json::<Consumer>().await?;
+    // The key is part of the path, so only the route is logged.
+    debug!(
+        "Fetching consumer from: {}/key-auths/{{key}}/consumer",
+        base_url
+    );
+    let url = format!(
+        "{}/key-auths/{}/consumer",
+        base_url,
+        api_key.expose_secret()
+    );
+    let request = http_client().client().get(&url).build()?;
+    let response = http_client()
+        .execute_with_template(request, "/key-auths/{key}/consumer")
+        .await?;
+    add_span_event_on_active_span(SpanEvent::new("kong responded").with_attribute(
+        AttributeVisibility::Default,
+        "http.response.status_code",
+        i64::from(response.status().as_u16()),
+    ));
+    let consumer = response.json::<Consumer>().await?;
     if !consumer.is_valid() {
         return Err(
             SrvErrorKind::Custom(StatusCode::UNAUTHORIZED, "Invalid API key".into()).into(),
@@ -47,7 +89,8 @@ async fn get_profile(api_key: String) -> Result<Value, SrvError> {
     }))
 }
 
-#[tracing::instrument(skip(query))]
+#[traced(name = "validate_request", visibility = "user")]
+#[tracing::instrument(skip(query, payload))]
 pub async fn validate_request(
     Query(query): Query<ApiKeyQuery>,
     Json(payload): Json<HashMap<String, HashMap<String, String>>>,
@@ -60,31 +103,143 @@ pub async fn validate_request(
         StatusCode::UNAUTHORIZED,
         "authorization is required".into(),
     ))?;
-    let token = token.split("Bearer ").nth(1).unwrap_or_default();
+    let token = Secret::new(
+        token
+            .split("Bearer ")
+            .nth(1)
+            .unwrap_or_default()
+            .to_string(),
+    );
+    add_span_event_on_active_span(
+        SpanEvent::new("credential extracted")
+            .with_attribute(AttributeVisibility::Default, "credential.type", "bearer")
+            .with_attribute(
+                AttributeVisibility::Default,
+                "credential.empty",
+                token.expose_secret().is_empty(),
+            ),
+    );
     debug!(
-        token = token,
-        body = format!("{:?}", payload),
-        query = format!("{:?}", query),
+        token = ?token,
+        headers = ?header_redactor().redact(headers),
+        query = ?query,
         "receiving request"
     );
-    let profile = get_profile(token.to_string()).await?;
-    Ok(Json(profile))
+    // Hasura forwards the headers of the end-user request, which may carry the trace of the frontend.
+    // Link it, so that the frontend trace connects through to its auth decision.
+    let profile = global_tracer()
+        .in_span_async_with_link(
+            "authorize",
+            "authorize",
+            SpanVisibility::User,
+            SpanLink::from_headers(headers),
+            || Box::pin(get_profile(token)),
+        )
+        .await;
+    if let Err(error) = &profile {
+        record_exception_on_active_span(error);
+    }
+    Ok(Json(profile?))
 }
 
 #[cfg(test)]
 mod tests {
+    use opentelemetry::trace::{FutureExt, Span, TraceContextExt, TraceId, Tracer, TracerProvider};
+    use opentelemetry::Context;
+    use tracing_ext::testing::{CapturedLines, InMemorySpanExporter};
+
     use super::*;
     use tracing::info;
 
     #[tokio::test]
     async fn test_get_profile() {
         dotenvy::dotenv().ok();
-        let api_key = "R78FanFgeJ7Wm63gvopqOf8MswEwepeN".to_string();
+        let api_key = Secret::new("R78FanFgeJ7Wm63gvopqOf8MswEwepeN".to_string());
         let profile = get_profile(api_key).await.unwrap();
         assert!(profile.is_object());
         info!("Test profile: {:?}", profile);
     }
 
+    /// Runs `future` in a new trace, returning its output and the trace ID, so that the assertions
+    /// only look at the spans of the test.
+    async fn in_new_trace<T>(
+        exporter: &InMemorySpanExporter,
+        future: impl std::future::Future<Output = T>,
+    ) -> (T, TraceId) {
+        let span = exporter.tracer_provider().tracer("test").start("test");
+        let trace_id = span.span_context().trace_id();
+        let output = future.with_context(Context::current_with_span(span)).await;
+        (output, trace_id)
+    }
+
+    #[tokio::test]
+    async fn test_validate_request_span() {
+        let exporter = InMemorySpanExporter::global();
+
+        let query: ApiKeyQuery = serde_json::from_value(json!({})).unwrap();
+        let (result, trace_id) = in_new_trace(
+            &exporter,
+            validate_request(Query(query), Json(HashMap::new())),
+        )
+        .await;
+        assert!(result.is_err());
+        exporter
+            .assert_span_in_trace("validate_request", trace_id)
+            .has_attribute("internal.visibility", "user")
+            .has_error_description("headers are required");
+    }
+
+    #[tokio::test]
+    async fn test_authorize_span_without_link() {
+        let exporter = InMemorySpanExporter::global();
+
+        let query: ApiKeyQuery = serde_json::from_value(json!({})).unwrap();
+        let payload = HashMap::from([(
+            "headers".to_string(),
+            HashMap::from([("authorization".to_string(), "Bearer t0k3n".to_string())]),
+        )]);
+        let (_, trace_id) =
+            in_new_trace(&exporter, validate_request(Query(query), Json(payload))).await;
+
+        // The span is created even when the headers carry no trace to link to.
+        let request_span = exporter.assert_span_in_trace("validate_request", trace_id);
+        let authorize = exporter.assert_span_in_trace("authorize", trace_id);
+        authorize.has_parent(&request_span.span().span_context);
+        assert!(authorize.span().links.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_validate_request_logs_no_credentials() {
+        let lines = CapturedLines::new();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(lines.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let query: ApiKeyQuery = serde_json::from_value(json!({"x-api-key": "qu3ry-k3y"})).unwrap();
+        let payload = HashMap::from([(
+            "headers".to_string(),
+            HashMap::from([
+                (
+                    "authorization".to_string(),
+                    "Bearer b3ar3r-t0k3n".to_string(),
+                ),
+                ("cookie".to_string(), "session=c00k13".to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+            ]),
+        )]);
+        let _ = validate_request(Query(query), Json(payload)).await;
+
+        let output = lines.output();
+        assert!(output.contains("receiving request"), "{output}");
+        assert!(output.contains("application/json"), "{output}");
+        for secret in ["qu3ry-k3y", "b3ar3r-t0k3n", "c00k13"] {
+            assert!(!output.contains(secret), "{secret} logged in: {output}");
+        }
+    }
+
     #[test]
     fn test_consumer_validation() {
         let valid_consumer = Consumer {
@@ -1,11 +1,82 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
 #[derive(Debug, Parser)]
 pub struct ServerCli {
+    /// The traces exporter: otlp, zipkin or none.
+    #[arg(
+        long,
+        value_name = "OTEL_TRACES_EXPORTER",
+        env = "OTEL_TRACES_EXPORTER",
+        default_value = "otlp"
+    )]
+    pub traces_exporter: String,
+
     /// The OpenTelemetry collector endpoint.
     #[arg(long, value_name = "OTLP_ENDPOINT", env = "OTLP_ENDPOINT")]
     pub otlp_endpoint: Option<String>,
 
+    /// The OpenTelemetry collector protocol: grpc, http/protobuf or http/json.
+    #[arg(
+        long,
+        value_name = "OTEL_EXPORTER_OTLP_PROTOCOL",
+        env = "OTEL_EXPORTER_OTLP_PROTOCOL",
+        default_value = "grpc"
+    )]
+    pub otlp_protocol: String,
+
+    /// Headers sent to the OpenTelemetry collector, as `key1=value1,key2=value2`.
+    #[arg(
+        long,
+        value_name = "OTEL_EXPORTER_OTLP_HEADERS",
+        env = "OTEL_EXPORTER_OTLP_HEADERS"
+    )]
+    pub otlp_headers: Option<String>,
+
+    /// Compression of the requests to the OpenTelemetry collector: gzip or zstd.
+    #[arg(
+        long,
+        value_name = "OTEL_EXPORTER_OTLP_COMPRESSION",
+        env = "OTEL_EXPORTER_OTLP_COMPRESSION"
+    )]
+    pub otlp_compression: Option<String>,
+
+    /// Path to a PEM encoded CA certificate to verify the OpenTelemetry collector.
+    #[arg(
+        long,
+        value_name = "OTEL_EXPORTER_OTLP_CERTIFICATE",
+        env = "OTEL_EXPORTER_OTLP_CERTIFICATE"
+    )]
+    pub otlp_certificate: Option<PathBuf>,
+
+    /// Timeout of the requests to the OpenTelemetry collector in milliseconds.
+    #[arg(
+        long,
+        value_name = "OTEL_EXPORTER_OTLP_TIMEOUT",
+        env = "OTEL_EXPORTER_OTLP_TIMEOUT",
+        default_value = "10000"
+    )]
+    pub otlp_timeout_ms: u64,
+
+    /// The Zipkin collector endpoint.
+    #[arg(
+        long,
+        value_name = "OTEL_EXPORTER_ZIPKIN_ENDPOINT",
+        env = "OTEL_EXPORTER_ZIPKIN_ENDPOINT",
+        default_value = "http://localhost:9411/api/v2/spans"
+    )]
+    pub zipkin_endpoint: String,
+
+    /// Timeout of the requests to the Zipkin collector in milliseconds.
+    #[arg(
+        long,
+        value_name = "OTEL_EXPORTER_ZIPKIN_TIMEOUT",
+        env = "OTEL_EXPORTER_ZIPKIN_TIMEOUT",
+        default_value = "10000"
+    )]
+    pub zipkin_timeout_ms: u64,
+
     /// Log traces to stdout.
     #[arg(
         long,
@@ -15,6 +86,16 @@ pub struct ServerCli {
     )]
     pub export_traces_stdout: bool,
 
+    /// Comma-separated propagators: tracecontext, b3, b3multi, jaeger, baggage, traceresponse or
+    /// none.
+    #[arg(
+        long,
+        value_name = "OTEL_PROPAGATORS",
+        env = "OTEL_PROPAGATORS",
+        default_value = "tracecontext,b3multi,baggage,traceresponse"
+    )]
+    pub propagators: String,
+
     /// Propagate caller baggage.
     #[arg(
         long,
@@ -24,6 +105,231 @@ pub struct ServerCli {
     )]
     pub propagate_caller_baggage: bool,
 
+    /// Comma-separated baggage keys copied onto the spans, a trailing * matches a prefix, e.g.
+    /// tenant.id,app.*, or * for all keys. No key is copied when unset.
+    #[arg(
+        long,
+        value_name = "BAGGAGE_ATTRIBUTE_KEYS",
+        env = "BAGGAGE_ATTRIBUTE_KEYS"
+    )]
+    pub baggage_attribute_keys: Option<String>,
+
+    /// Maximum number of baggage entries copied onto a span.
+    #[arg(
+        long,
+        value_name = "BAGGAGE_ATTRIBUTE_MAX_ENTRIES",
+        env = "BAGGAGE_ATTRIBUTE_MAX_ENTRIES",
+        default_value = "64"
+    )]
+    pub baggage_attribute_max_entries: usize,
+
+    /// Maximum length in bytes of the baggage values copied onto the spans.
+    #[arg(
+        long,
+        value_name = "BAGGAGE_ATTRIBUTE_MAX_VALUE_LENGTH",
+        env = "BAGGAGE_ATTRIBUTE_MAX_VALUE_LENGTH",
+        default_value = "256"
+    )]
+    pub baggage_attribute_max_value_length: usize,
+
+    /// Prefix added to the keys of the baggage entries copied onto the spans, e.g. baggage.
+    #[arg(
+        long,
+        value_name = "BAGGAGE_ATTRIBUTE_KEY_PREFIX",
+        env = "BAGGAGE_ATTRIBUTE_KEY_PREFIX"
+    )]
+    pub baggage_attribute_key_prefix: Option<String>,
+
+    /// Only export traces with errors, slow traces and a ratio of the remaining ones.
+    #[arg(
+        long,
+        value_name = "TAIL_SAMPLING",
+        env = "TAIL_SAMPLING",
+        default_value = "false"
+    )]
+    pub tail_sampling: bool,
+
+    /// Tail sampling latency threshold in milliseconds above which a trace is always exported.
+    #[arg(
+        long,
+        value_name = "TAIL_SAMPLING_LATENCY_THRESHOLD_MS",
+        env = "TAIL_SAMPLING_LATENCY_THRESHOLD_MS",
+        default_value = "500"
+    )]
+    pub tail_sampling_latency_threshold_ms: u64,
+
+    /// Tail sampling ratio of healthy traces to export.
+    #[arg(
+        long,
+        value_name = "TAIL_SAMPLING_RATIO",
+        env = "TAIL_SAMPLING_RATIO",
+        default_value = "0.1"
+    )]
+    pub tail_sampling_ratio: f64,
+
+    /// Redact internal attributes and secret values before exporting traces, e.g. to a vendor
+    /// readable by user-facing teams.
+    #[arg(
+        long,
+        value_name = "REDACT_TELEMETRY",
+        env = "REDACT_TELEMETRY",
+        default_value = "false"
+    )]
+    pub redact_telemetry: bool,
+
+    /// What to do with the internal attributes when redacting: drop, hash or keep.
+    #[arg(
+        long,
+        value_name = "REDACT_INTERNAL_ATTRIBUTES",
+        env = "REDACT_INTERNAL_ATTRIBUTES",
+        default_value = "drop"
+    )]
+    pub redact_internal_attributes: String,
+
+    /// Comma-separated attribute keys to drop when redacting, e.g. user.email,tenant.*
+    #[arg(
+        long,
+        value_name = "REDACT_ATTRIBUTE_KEYS",
+        env = "REDACT_ATTRIBUTE_KEYS"
+    )]
+    pub redact_attribute_keys: Option<String>,
+
+    /// Comma-separated headers whose values are masked in the logs, in addition to authorization,
+    /// cookie and the other credential headers, e.g. x-tenant-token.
+    #[arg(long, value_name = "REDACT_LOG_HEADERS", env = "REDACT_LOG_HEADERS")]
+    pub redact_log_headers: Option<String>,
+
+    /// Export rate, error and duration metrics of the spans to the OTLP collector.
+    #[arg(
+        long,
+        value_name = "SPAN_METRICS",
+        env = "SPAN_METRICS",
+        default_value = "false"
+    )]
+    pub span_metrics: bool,
+
+    /// Interval between two exports of the metrics in milliseconds.
+    #[arg(
+        long,
+        value_name = "OTEL_METRIC_EXPORT_INTERVAL",
+        env = "OTEL_METRIC_EXPORT_INTERVAL",
+        default_value = "60000"
+    )]
+    pub metric_export_interval_ms: u64,
+
+    /// Format of the log lines: text, compact or json.
+    #[arg(
+        long,
+        value_name = "LOG_FORMAT",
+        env = "LOG_FORMAT",
+        default_value = "text"
+    )]
+    pub log_format: String,
+
+    /// Path of a log file written alongside the standard streams, e.g.
+    /// /var/log/auth-webhook/auth-webhook.log. No log file is written when unset.
+    #[arg(long, value_name = "LOG_FILE", env = "LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// When the log file is rotated: hourly, daily, never or a size, e.g. 100MB.
+    #[arg(
+        long,
+        value_name = "LOG_FILE_ROTATION",
+        env = "LOG_FILE_ROTATION",
+        default_value = "daily"
+    )]
+    pub log_file_rotation: String,
+
+    /// Number of rotated log files to keep, 0 keeps all of them.
+    #[arg(
+        long,
+        value_name = "LOG_FILE_MAX_FILES",
+        env = "LOG_FILE_MAX_FILES",
+        default_value = "7"
+    )]
+    pub log_file_max_files: usize,
+
+    /// Token required to call the admin endpoints, e.g. to change the log level at runtime. The
+    /// admin endpoints are disabled when unset.
+    #[arg(long, value_name = "ADMIN_TOKEN", env = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Log filter directives toggled on and off by SIGUSR1, e.g. auth_webhook=debug.
+    #[arg(
+        long,
+        value_name = "LOG_LEVEL_TOGGLE_DIRECTIVES",
+        env = "LOG_LEVEL_TOGGLE_DIRECTIVES",
+        default_value = "debug"
+    )]
+    pub log_level_toggle_directives: String,
+
+    /// The deployment environment reported with the telemetry, e.g. production.
+    #[arg(
+        long,
+        value_name = "DEPLOYMENT_ENVIRONMENT",
+        env = "DEPLOYMENT_ENVIRONMENT"
+    )]
+    pub deployment_environment: Option<String>,
+
+    /// Timeout of each readiness check in milliseconds.
+    #[arg(
+        long,
+        value_name = "READINESS_TIMEOUT_MS",
+        env = "READINESS_TIMEOUT_MS",
+        default_value = "2000"
+    )]
+    pub readiness_timeout_ms: u64,
+
+    /// Delay in milliseconds between reporting not ready on the shutdown signal and draining, so
+    /// that the load balancer stops routing requests first.
+    #[arg(
+        long,
+        value_name = "SHUTDOWN_DELAY_MS",
+        env = "SHUTDOWN_DELAY_MS",
+        default_value = "0"
+    )]
+    pub shutdown_delay_ms: u64,
+
+    /// Time in milliseconds given to the in-flight requests to finish on shutdown, after which the
+    /// process exits. The metrics and the traces are then flushed, for up to 5 seconds each, so the
+    /// termination grace period must exceed SHUTDOWN_DELAY_MS + DRAIN_TIMEOUT_MS + 10 seconds.
+    /// The default fits in the default Kubernetes grace period of 30 seconds.
+    #[arg(
+        long,
+        value_name = "DRAIN_TIMEOUT_MS",
+        env = "DRAIN_TIMEOUT_MS",
+        default_value = "15000"
+    )]
+    pub drain_timeout_ms: u64,
+
+    /// Time in milliseconds given to a request before answering 504 Gateway Timeout.
+    #[arg(
+        long,
+        value_name = "REQUEST_TIMEOUT_MS",
+        env = "REQUEST_TIMEOUT_MS",
+        default_value = "30000"
+    )]
+    pub request_timeout_ms: u64,
+
+    /// Maximum size of a request body in bytes.
+    #[arg(
+        long,
+        value_name = "REQUEST_BODY_LIMIT",
+        env = "REQUEST_BODY_LIMIT",
+        default_value = "1048576"
+    )]
+    pub request_body_limit: usize,
+
+    /// Maximum number of requests handled concurrently, the requests above it are answered with
+    /// 503 Service Unavailable.
+    #[arg(
+        long,
+        value_name = "CONCURRENCY_LIMIT",
+        env = "CONCURRENCY_LIMIT",
+        default_value = "1024"
+    )]
+    pub concurrency_limit: usize,
+
     /// Port.
     #[arg(long, value_name = "PORT", env = "PORT")]
     pub port: u16,
@@ -6,6 +6,7 @@ use axum::{
 use serde_json::json;
 use std::fmt::{Debug, Display};
 use tracing_error::SpanTrace;
+use tracing_ext::{ErrorVisibility, TraceableError};
 
 #[allow(dead_code)]
 pub type SrvResult<T> = Result<T, SrvError>;
@@ -30,7 +31,14 @@ pub enum SrvErrorKind {
     Any(#[from] anyhow::Error),
 
     #[error("reqwest error. {:?}", .0)]
-    ReqwestError(#[from] reqwest::Error),
+    ReqwestError(reqwest::Error),
+}
+
+/// Strips the URL, which contains the API key in the path, from the error message.
+impl From<reqwest::Error> for SrvErrorKind {
+    fn from(error: reqwest::Error) -> Self {
+        SrvErrorKind::ReqwestError(error.without_url())
+    }
 }
 
 #[derive(Debug)]
@@ -68,10 +76,23 @@ where
     }
 }
 
+impl TraceableError for SrvError {
+    fn visibility(&self) -> ErrorVisibility {
+        match &self.error_kind {
+            SrvErrorKind::Any(_) | SrvErrorKind::ReqwestError(_) => ErrorVisibility::Internal,
+            _ => ErrorVisibility::User,
+        }
+    }
+
+    fn description(&self) -> String {
+        self.error_kind.to_string()
+    }
+}
+
 impl IntoResponse for SrvError {
     fn into_response(self) -> Response {
         let status_code = match &self.error_kind {
-            SrvErrorKind::Custom(code, _) => code.clone(),
+            SrvErrorKind::Custom(code, _) => *code,
             SrvErrorKind::NotFound(_) => StatusCode::NOT_FOUND,
             SrvErrorKind::Any(_) => StatusCode::INTERNAL_SERVER_ERROR,
             SrvErrorKind::Unauthorized(_) => StatusCode::UNAUTHORIZED,
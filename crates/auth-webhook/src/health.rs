@@ -0,0 +1,228 @@
+//! Liveness and readiness endpoints.
+//!
+//! - `GET /healthz` answers `{"status": "ok"}` as long as the process serves requests, without
+//!   running any check,
+//! - `GET /readyz` runs the readiness checks, e.g. a probe of the Kong Admin API, and answers
+//!   `503 Service Unavailable` if one fails or once the shutdown signal is received, so that
+//!   Kubernetes stops routing requests before the server drains. It answers with a JSON breakdown
+//!   of the checks.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, response::Json, routing::get, Router};
+use serde_json::{json, Map, Value};
+
+/// A dependency the webhook needs to serve requests.
+pub trait ReadinessCheck: Send + Sync {
+    /// Name of the check in the JSON breakdown.
+    fn name(&self) -> &'static str;
+
+    /// Checks the dependency, returning why it is not ready on failure.
+    fn check(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send + '_>>;
+}
+
+/// Probes the `/status` endpoint of the Kong Admin API.
+pub struct KongStatusCheck {
+    status_url: String,
+}
+
+impl KongStatusCheck {
+    pub fn new(kong_url: &str) -> Self {
+        Self {
+            status_url: format!("{}/status", kong_url.trim_end_matches('/')),
+        }
+    }
+}
+
+impl ReadinessCheck for KongStatusCheck {
+    fn name(&self) -> &'static str {
+        "kong"
+    }
+
+    fn check(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send + '_>> {
+        Box::pin(async move {
+            // Probes are not traced, they would drown the traces of the requests.
+            let response = crate::auth_handler::http_client()
+                .client()
+                .get(&self.status_url)
+                .send()
+                .await
+                .map_err(|error| error.without_url().to_string())?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("Kong status returned {}", response.status()))
+            }
+        })
+    }
+}
+
+/// The readiness of the webhook, shared by the endpoints and the shutdown handler.
+pub struct Health {
+    checks: Vec<Box<dyn ReadinessCheck>>,
+    timeout: Duration,
+    shutting_down: AtomicBool,
+}
+
+impl Health {
+    /// Creates the readiness running the `checks`, each failing after `timeout`.
+    pub fn new(checks: Vec<Box<dyn ReadinessCheck>>, timeout: Duration) -> Self {
+        Self {
+            checks,
+            timeout,
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Reports not ready from now on, as the server is about to drain.
+    pub fn mark_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Runs the checks, returning whether all passed and their breakdown.
+    async fn readiness(&self) -> (bool, Value) {
+        let mut ready = true;
+        let mut checks = Map::new();
+        if self.shutting_down.load(Ordering::SeqCst) {
+            ready = false;
+            checks.insert(
+                "shutdown".to_string(),
+                json!({"status": "fail", "error": "shutting down"}),
+            );
+        }
+        for check in &self.checks {
+            let start = Instant::now();
+            let result = match tokio::time::timeout(self.timeout, check.check()).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("timed out after {}ms", self.timeout.as_millis())),
+            };
+            let latency_ms = start.elapsed().as_millis() as u64;
+            let breakdown = match result {
+                Ok(()) => json!({"status": "ok", "latency_ms": latency_ms}),
+                Err(error) => {
+                    ready = false;
+                    json!({"status": "fail", "latency_ms": latency_ms, "error": error})
+                }
+            };
+            checks.insert(check.name().to_string(), breakdown);
+        }
+        (ready, Value::Object(checks))
+    }
+}
+
+/// The `/healthz` and `/readyz` routes.
+pub fn router(health: Arc<Health>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(health)
+}
+
+async fn healthz() -> Json<Value> {
+    Json(json!({"status": "ok"}))
+}
+
+async fn readyz(State(health): State<Arc<Health>>) -> (StatusCode, Json<Value>) {
+    let (ready, checks) = health.readiness().await;
+    let (status_code, status) = if ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+    (
+        status_code,
+        Json(json!({"status": status, "checks": checks})),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StaticCheck(Result<(), String>);
+
+    impl ReadinessCheck for StaticCheck {
+        fn name(&self) -> &'static str {
+            "static"
+        }
+
+        fn check(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send + '_>> {
+            Box::pin(std::future::ready(self.0.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let health = Arc::new(Health::new(
+            vec![Box::new(StaticCheck(Ok(())))],
+            Duration::from_secs(1),
+        ));
+        let (status_code, Json(body)) = readyz(State(health.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body["checks"]["static"]["status"], "ok");
+
+        health.mark_shutting_down();
+        let (status_code, Json(body)) = readyz(State(health)).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(body["checks"]["shutdown"]["status"], "fail");
+    }
+
+    /// Starts a fake Kong Admin API answering `/status` with `status_code`, returning its URL.
+    async fn serve_kong(status_code: StatusCode) -> String {
+        let router = Router::new().route("/status", get(move || async move { status_code }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{address}/")
+    }
+
+    #[tokio::test]
+    async fn test_kong_status_check() {
+        let check = KongStatusCheck::new(&serve_kong(StatusCode::OK).await);
+        assert_eq!(check.check().await, Ok(()));
+
+        let check = KongStatusCheck::new(&serve_kong(StatusCode::SERVICE_UNAVAILABLE).await);
+        assert_eq!(
+            check.check().await,
+            Err("Kong status returned 503 Service Unavailable".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kong_status_check_timeout() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // A Kong accepting the connections and never replying.
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let health = Arc::new(Health::new(
+            vec![Box::new(KongStatusCheck::new(&format!("http://{address}")))],
+            Duration::from_millis(100),
+        ));
+
+        let (status_code, Json(body)) = readyz(State(health)).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["kong"]["status"], "fail");
+        assert_eq!(body["checks"]["kong"]["error"], "timed out after 100ms");
+    }
+
+    #[tokio::test]
+    async fn test_failing_check() {
+        let health = Arc::new(Health::new(
+            vec![Box::new(StaticCheck(Err("unreachable".into())))],
+            Duration::from_secs(1),
+        ));
+        let (status_code, Json(body)) = readyz(State(health)).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["static"]["error"], "unreachable");
+    }
+}
@@ -1,18 +1,23 @@
-use chrono;
-use std::net;
-use tracing::info;
+use std::{net, sync::Arc, time::Duration};
+use tracing::{info, warn};
 
 use axum::{routing::post, Router};
+use axum_ext::{MiddlewareStack, Signal};
 use clap::Parser;
 use tower_http::trace::TraceLayer;
 
 use tracing_ext::{
-    graphql_request_tracing_middleware, init_tracing, ExportTracesStdout, PropagateBaggage,
+    graphql_request_tracing_middleware, init_meter_provider, init_tracing, parse_otlp_headers,
+    parse_propagators, BaggageAttributesConfig, ExportTracesStdout, HeaderRedactor, MeterProvider,
+    OtlpExporterConfig, PropagateBaggage, RedactionConfig, RollingFileConfig, ServiceInfo,
+    SpanExporterConfig, TailSamplingConfig, TracingConfig, ZipkinExporterConfig,
 };
 
+mod admin;
 mod auth_handler;
 mod cli;
 mod errors;
+mod health;
 mod validator;
 
 #[tokio::main]
@@ -30,36 +35,221 @@ async fn main() -> anyhow::Result<()> {
     } else {
         PropagateBaggage::Disable
     };
+    let exporter = match opt.traces_exporter.as_str() {
+        "otlp" => opt
+            .otlp_endpoint
+            .as_deref()
+            .map(|endpoint| -> anyhow::Result<_> {
+                let otlp = OtlpExporterConfig::new(endpoint)
+                    .with_protocol(opt.otlp_protocol.parse()?)
+                    .with_headers(parse_otlp_headers(
+                        opt.otlp_headers.as_deref().unwrap_or_default(),
+                    ))
+                    .with_compression(
+                        opt.otlp_compression
+                            .as_deref()
+                            .map(str::parse)
+                            .transpose()?,
+                    )
+                    .with_ca_certificate(opt.otlp_certificate.clone())
+                    .with_timeout(Duration::from_millis(opt.otlp_timeout_ms));
+                Ok(SpanExporterConfig::Otlp(otlp))
+            }),
+        "zipkin" => Some(Ok(SpanExporterConfig::Zipkin(
+            ZipkinExporterConfig::new(&opt.zipkin_endpoint)
+                .with_timeout(Duration::from_millis(opt.zipkin_timeout_ms)),
+        ))),
+        "none" => None,
+        other => anyhow::bail!("unsupported traces exporter: {other}"),
+    }
+    .transpose()?;
+    let export_traces = exporter.is_some();
+    let propagators = parse_propagators(&opt.propagators)?;
+    let tail_sampling = opt.tail_sampling.then(|| TailSamplingConfig {
+        latency_threshold: Duration::from_millis(opt.tail_sampling_latency_threshold_ms),
+        ratio: opt.tail_sampling_ratio,
+        ..Default::default()
+    });
+    let redaction = if opt.redact_telemetry {
+        let internal_attributes = match opt.redact_internal_attributes.as_str() {
+            "keep" => None,
+            action => Some(action.parse()?),
+        };
+        Some(RedactionConfig {
+            internal_attributes,
+            denylisted_keys: split_list(opt.redact_attribute_keys.as_deref()),
+            ..Default::default()
+        })
+    } else {
+        None
+    };
+    let (allowed_key_prefixes, allowed_keys) = split_list(opt.baggage_attribute_keys.as_deref())
+        .into_iter()
+        .partition(|key| key.ends_with('*'));
+    let baggage_attributes = BaggageAttributesConfig {
+        allowed_keys,
+        allowed_key_prefixes: allowed_key_prefixes
+            .into_iter()
+            .map(|prefix| prefix.trim_end_matches('*').to_string())
+            .collect(),
+        max_entries: opt.baggage_attribute_max_entries,
+        max_value_length: opt.baggage_attribute_max_value_length,
+        key_prefix: opt.baggage_attribute_key_prefix.clone(),
+    };
+
+    let log_file = opt
+        .log_file
+        .clone()
+        .map(|path| -> anyhow::Result<_> {
+            Ok(RollingFileConfig::new(path)
+                .with_rotation(opt.log_file_rotation.parse()?)
+                .with_max_files((opt.log_file_max_files > 0).then_some(opt.log_file_max_files)))
+        })
+        .transpose()?;
 
-    init_tracing(
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION"),
-        opt.otlp_endpoint.as_deref(),
-        propagate_caller_baggage,
-        export_traces_stdout,
+    let service = ServiceInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+        .with_deployment_environment(opt.deployment_environment.clone());
+
+    let meter_provider = match (&exporter, opt.span_metrics) {
+        (_, false) => None,
+        (Some(SpanExporterConfig::Otlp(otlp)), true) => Some(init_meter_provider(
+            service.resource(),
+            otlp,
+            Duration::from_millis(opt.metric_export_interval_ms),
+        )?),
+        (_, true) => anyhow::bail!("span metrics require the otlp traces exporter"),
+    };
+    let span_metrics = meter_provider
+        .as_ref()
+        .map(|meter_provider| meter_provider.meter(env!("CARGO_PKG_NAME")));
+
+    let log_level = init_tracing(
+        TracingConfig::new(service)
+            .with_exporter(exporter)
+            .with_propagators(propagators)
+            .with_propagate_caller_baggage(propagate_caller_baggage)
+            .with_baggage_attributes(baggage_attributes)
+            .with_export_traces_stdout(export_traces_stdout)
+            .with_tail_sampling(tail_sampling)
+            .with_redaction(redaction)
+            .with_log_format(opt.log_format.parse()?)
+            .with_log_file(log_file)
+            .with_span_metrics(span_metrics),
     )?;
 
-    let mut router = Router::new()
-        .route("/validate-request", post(auth_handler::validate_request))
+    auth_handler::init_header_redactor(
+        HeaderRedactor::default()
+            .with_sensitive_headers(split_list(opt.redact_log_headers.as_deref())),
+    );
+
+    let router = Router::new().route("/validate-request", post(auth_handler::validate_request));
+    let mut router = MiddlewareStack::new()
+        .with_request_timeout(Duration::from_millis(opt.request_timeout_ms))
+        .with_body_limit(opt.request_body_limit)
+        .with_concurrency_limit(opt.concurrency_limit)
+        .apply(router)
         .layer(axum::middleware::from_fn(
             graphql_request_tracing_middleware,
         ));
-    if let Some(_) = opt.otlp_endpoint {
+    if export_traces {
         router = router.layer(TraceLayer::new_for_http());
     }
-
+    let health = Arc::new(health::Health::new(
+        vec![Box::new(health::KongStatusCheck::new(&opt.kong_url))],
+        Duration::from_millis(opt.readiness_timeout_ms),
+    ));
+    router = router.merge(health::router(health.clone()));
+    if let Some(admin_token) = opt.admin_token.clone() {
+        router = router.merge(admin::router(admin_token, log_level.clone()));
+    }
     let host = net::IpAddr::V6(net::Ipv6Addr::UNSPECIFIED);
 
     let address = (host, port);
     let listener = tokio::net::TcpListener::bind(address).await?;
 
-    let server = axum::serve(listener, router.into_make_service());
+    let lifecycle = axum_ext::Lifecycle::new()
+        .with_drain_timeout(Duration::from_millis(opt.drain_timeout_ms))
+        .on_shutdown("flush metrics", move || async move {
+            let Some(meter_provider) = meter_provider else {
+                return;
+            };
+            match tokio::task::spawn_blocking(move || meter_provider.shutdown()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(error)) => warn!(%error, "failed to flush the metrics"),
+                Err(error) => warn!(%error, "failed to flush the metrics"),
+            }
+        })
+        .on_shutdown("flush traces", || async {
+            // The batch span processor blocks while exporting the last spans.
+            if let Err(error) = tokio::task::spawn_blocking(tracing_ext::shutdown_tracer).await {
+                warn!(%error, "failed to flush the traces");
+            }
+        });
+
+    #[cfg(unix)]
+    lifecycle.spawn(
+        log_level_signal_handlers(log_level, opt.log_level_toggle_directives.clone())
+            .listen(lifecycle.cancellation_token())?,
+    );
+
+    let server = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(lifecycle.cancellation_token().cancelled_owned());
     info!("Server started on port {}", port);
-    server
-        .with_graceful_shutdown(axum_ext::shutdown_signal_with_handler(|| async move {
-            info!("Received shutdown signal at {}", chrono::Local::now());
-        }))
+    let shutdown_delay = Duration::from_millis(opt.shutdown_delay_ms);
+    let shutdown_signal =
+        axum_ext::shutdown_signals(&[Signal::Interrupt, Signal::Terminate, Signal::Quit])?;
+    lifecycle
+        .run(
+            async move {
+                let signal = shutdown_signal.await;
+                info!(%signal, "Received shutdown signal at {}", chrono::Local::now());
+                health.mark_shutting_down();
+                tokio::time::sleep(shutdown_delay).await;
+            },
+            server,
+        )
         .await?;
     info!("Server shutdown at {}", chrono::Local::now());
     Ok(())
 }
+
+/// Toggles the log filter between the startup directives and `directives` on every SIGUSR1, and
+/// restores the startup directives on SIGHUP.
+#[cfg(unix)]
+fn log_level_signal_handlers(
+    log_level: tracing_ext::LogLevelHandle,
+    directives: String,
+) -> axum_ext::SignalHandlers {
+    let reset_log_level = log_level.clone();
+    axum_ext::SignalHandlers::new()
+        .on(Signal::User1, move || {
+            match log_level.toggle(&directives) {
+                Ok(current) => info!(directives = current, "log level toggled"),
+                Err(error) => warn!(%error, "failed to toggle the log level"),
+            }
+            std::future::ready(())
+        })
+        .on(Signal::Hangup, move || {
+            match reset_log_level.reset() {
+                Ok(()) => info!(
+                    directives = reset_log_level.initial_directives(),
+                    "log level reset"
+                ),
+                Err(error) => warn!(%error, "failed to reset the log level"),
+            }
+            std::future::ready(())
+        })
+}
+
+/// Splits a comma-separated list, ignoring empty items.
+fn split_list(list: Option<&str>) -> Vec<String> {
+    list.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
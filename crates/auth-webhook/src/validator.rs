@@ -1,14 +1,26 @@
+use std::fmt;
+
 use axum_extra::headers::HeaderMap;
 use serde::{Deserialize, Serialize};
+use tracing_ext::Secret;
 
 use crate::errors::{SrvError, SrvErrorKind};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ApiKeyQuery {
     #[serde(rename = "x-api-key")]
     api_key: Option<String>,
 }
 
+/// Masks the API key, so that the query can be logged.
+impl fmt::Debug for ApiKeyQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKeyQuery")
+            .field("api_key", &self.api_key.as_ref().map(Secret::new))
+            .finish()
+    }
+}
+
 #[allow(dead_code)]
 pub fn api_key_validator(headers: HeaderMap, query: ApiKeyQuery) -> Result<String, SrvError> {
     // Extract API key from headers or query parameters
@@ -1,5 +1,15 @@
+mod lifecycle;
+mod middleware;
 mod shutdown;
+mod signal;
 
 // re-export things from OpenTelemetry to avoid library users importing their own version and
 // risking mismatches and multiple globals
-pub use shutdown::{shutdown_signal, shutdown_signal_with_handler};
+pub use lifecycle::{Lifecycle, DEFAULT_DRAIN_TIMEOUT, DEFAULT_HOOK_TIMEOUT};
+pub use middleware::{
+    MiddlewareStack, RequestId, DEFAULT_BODY_LIMIT, DEFAULT_CONCURRENCY_LIMIT,
+    DEFAULT_REQUEST_TIMEOUT, DEFAULT_RETRY_AFTER, REQUEST_ID_HEADER,
+};
+pub use shutdown::{shutdown_signal, shutdown_signal_with_handler, DEFAULT_SHUTDOWN_SIGNALS};
+pub use signal::{shutdown_signals, Signal, SignalError, SignalHandlers, SignalListener};
+pub use tokio_util::sync::CancellationToken;
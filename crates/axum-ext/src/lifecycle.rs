@@ -0,0 +1,276 @@
+//! This module provides the shutdown lifecycle of a server and of its background tasks.
+//!
+//! On the shutdown signal, [`Lifecycle::run`]:
+//! 1. cancels the [`CancellationToken`] of the lifecycle, which stops accepting connections when
+//!    passed to `with_graceful_shutdown` and tells the background tasks to stop,
+//! 2. waits for the in-flight requests and the background tasks spawned with [`Lifecycle::spawn`]
+//!    to finish, up to the drain timeout,
+//! 3. runs the shutdown hooks in the order they were registered, e.g. flushing the telemetry then
+//!    closing the caches, each up to the hook timeout,
+//! 4. exits the process if draining exceeded the drain timeout, as the remaining requests would
+//!    otherwise keep it alive.
+//!
+//! Kubernetes kills the process at the end of its termination grace period, 30 seconds by default.
+//! The grace period must therefore exceed the time the shutdown signal takes to complete after
+//! `SIGTERM`, e.g. a delay while reporting not ready, plus the drain timeout and the hook timeout
+//! of every hook. The default timeouts fit two hooks in 30 seconds, leaving 5 seconds for a delay.
+//!
+//! # Example:
+//! ```no_run
+//! use axum_ext::{shutdown_signal, Lifecycle};
+//!
+//! # async fn example(listener: tokio::net::TcpListener, router: axum::Router) -> std::io::Result<()> {
+//! let lifecycle = Lifecycle::new().on_shutdown("flush telemetry", || async {
+//!     // The tracer provider blocks while exporting the last spans.
+//!     let _ = tokio::task::spawn_blocking(tracing_ext::shutdown_tracer).await;
+//! });
+//! let server = axum::serve(listener, router)
+//!     .with_graceful_shutdown(lifecycle.cancellation_token().cancelled_owned());
+//! lifecycle.run(shutdown_signal(), server).await
+//! # }
+//! ```
+
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+/// Default time given to the in-flight requests and the background tasks to finish.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Default time given to each shutdown hook.
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Exit code of the process when draining exceeds the drain timeout.
+const DRAIN_TIMEOUT_EXIT_CODE: i32 = 1;
+
+type ShutdownHook = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// The shutdown lifecycle of a server and of its background tasks.
+pub struct Lifecycle {
+    token: CancellationToken,
+    tasks: TaskTracker,
+    drain_timeout: Duration,
+    hook_timeout: Duration,
+    hooks: Vec<(&'static str, ShutdownHook)>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            hook_timeout: DEFAULT_HOOK_TIMEOUT,
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Lifecycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lifecycle")
+            .field("token", &self.token)
+            .field("tasks", &self.tasks)
+            .field("drain_timeout", &self.drain_timeout)
+            .field("hook_timeout", &self.hook_timeout)
+            .field(
+                "hooks",
+                &self.hooks.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Lifecycle {
+    /// Creates a lifecycle with the default timeouts and no shutdown hooks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time given to the in-flight requests and the background tasks to finish.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Sets the time given to each shutdown hook.
+    pub fn with_hook_timeout(mut self, hook_timeout: Duration) -> Self {
+        self.hook_timeout = hook_timeout;
+        self
+    }
+
+    /// Registers a hook run on shutdown, after the hooks registered before it.
+    pub fn on_shutdown<F, Fut>(mut self, name: &'static str, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.push((name, Box::new(move || Box::pin(hook()))));
+        self
+    }
+
+    /// A token cancelled on the shutdown signal.
+    ///
+    /// The token is a child of the lifecycle's, so cancelling it doesn't shut the server down.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.child_token()
+    }
+
+    /// Spawns a background task the shutdown waits for, up to the drain timeout.
+    ///
+    /// The task should stop once its [`Lifecycle::cancellation_token`] is cancelled.
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.spawn(task)
+    }
+
+    /// Runs the `server` until the `shutdown_signal`, then shuts down.
+    ///
+    /// The `server` should shut down gracefully on the [`Lifecycle::cancellation_token`]. If it
+    /// stops on its own, e.g. on an error, the shutdown hooks still run.
+    ///
+    /// Exits the process if the server and the background tasks don't finish within the drain
+    /// timeout.
+    pub async fn run<S, E>(
+        self,
+        shutdown_signal: impl Future<Output = ()>,
+        server: S,
+    ) -> Result<(), E>
+    where
+        S: IntoFuture<Output = Result<(), E>>,
+    {
+        match self.shutdown(shutdown_signal, server).await {
+            Some(result) => result,
+            None => std::process::exit(DRAIN_TIMEOUT_EXIT_CODE),
+        }
+    }
+
+    /// Runs the `server` until the `shutdown_signal`, drains and runs the shutdown hooks,
+    /// returning `None` if draining exceeded the drain timeout.
+    async fn shutdown<S, E>(
+        self,
+        shutdown_signal: impl Future<Output = ()>,
+        server: S,
+    ) -> Option<Result<(), E>>
+    where
+        S: IntoFuture<Output = Result<(), E>>,
+    {
+        let server = server.into_future();
+        tokio::pin!(server);
+        let stopped = tokio::select! {
+            result = &mut server => Some(result),
+            _ = shutdown_signal => None,
+        };
+        self.token.cancel();
+        self.tasks.close();
+
+        let drained = match stopped {
+            Some(result) => {
+                warn!("server stopped before the shutdown signal, draining background tasks");
+                tokio::time::timeout(self.drain_timeout, self.tasks.wait())
+                    .await
+                    .ok()
+                    .map(|()| result)
+            }
+            None => {
+                info!(
+                    drain_timeout_ms = self.drain_timeout.as_millis() as u64,
+                    "draining in-flight requests and background tasks"
+                );
+                tokio::time::timeout(self.drain_timeout, async {
+                    let result = server.await;
+                    self.tasks.wait().await;
+                    result
+                })
+                .await
+                .ok()
+            }
+        };
+        if drained.is_none() {
+            warn!(
+                drain_timeout_ms = self.drain_timeout.as_millis() as u64,
+                pending_tasks = self.tasks.len(),
+                "draining exceeded the drain timeout"
+            );
+        }
+
+        for (name, hook) in self.hooks {
+            match tokio::time::timeout(self.hook_timeout, hook()).await {
+                Ok(()) => info!(hook = name, "shutdown hook completed"),
+                Err(_) => warn!(
+                    hook = name,
+                    hook_timeout_ms = self.hook_timeout.as_millis() as u64,
+                    "shutdown hook timed out"
+                ),
+            }
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A hook appending its `name` to `calls`.
+    fn record(
+        calls: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+    ) -> impl FnOnce() -> std::future::Ready<()> {
+        let calls = calls.clone();
+        move || {
+            calls.lock().unwrap().push(name);
+            std::future::ready(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let lifecycle = Lifecycle::new()
+            .on_shutdown("flush telemetry", record(&calls, "flush telemetry"))
+            .on_shutdown("close caches", record(&calls, "close caches"));
+        let token = lifecycle.cancellation_token();
+        let task_calls = calls.clone();
+        lifecycle.spawn(async move {
+            token.cancelled().await;
+            task_calls.lock().unwrap().push("background task");
+        });
+        let server_token = lifecycle.cancellation_token();
+        let server = async move {
+            server_token.cancelled().await;
+            Ok::<_, ()>(())
+        };
+
+        let result = lifecycle.shutdown(std::future::ready(()), server).await;
+        assert_eq!(result, Some(Ok(())));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["background task", "flush telemetry", "close caches"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let lifecycle = Lifecycle::new()
+            .with_drain_timeout(Duration::from_millis(10))
+            .on_shutdown("flush telemetry", record(&calls, "flush telemetry"));
+        let server = std::future::pending::<Result<(), ()>>();
+
+        let result = lifecycle.shutdown(std::future::ready(()), server).await;
+        assert_eq!(result, None);
+        // The telemetry is flushed even when draining exceeds the timeout.
+        assert_eq!(*calls.lock().unwrap(), ["flush telemetry"]);
+    }
+}
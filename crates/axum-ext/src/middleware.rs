@@ -0,0 +1,351 @@
+//! This module provides the middleware stack of a production server.
+//!
+//! [`MiddlewareStack::apply`] layers the routes of a router with, from the outermost:
+//! - the request ID, taken from the `x-request-id` request header or generated, set on the request,
+//!   the response and the active span,
+//! - a global concurrency limit shedding the requests above it with `503 Service Unavailable` and
+//!   a `Retry-After` header, rather than queueing them,
+//! - a per-request timeout answering `504 Gateway Timeout`,
+//! - a request body size limit answering `413 Payload Too Large`,
+//! - panic catching, answering `500 Internal Server Error` and recording the panic on the active
+//!   span.
+//!
+//! The errors are answered with a JSON body. Apply the stack inside the tracing middleware, so that
+//! the span of the request records them, and after adding the routes it applies to.
+//!
+//! # Example:
+//! ```
+//! use std::time::Duration;
+//!
+//! use axum::{routing::post, Router};
+//! use axum_ext::MiddlewareStack;
+//!
+//! let router = Router::new().route("/validate-request", post(|| async { "ok" }));
+//! let router = MiddlewareStack::new()
+//!     .with_request_timeout(Duration::from_secs(5))
+//!     .with_concurrency_limit(512)
+//!     .apply(router)
+//!     .layer(axum::middleware::from_fn(
+//!         tracing_ext::graphql_request_tracing_middleware,
+//!     ));
+//! # let _: Router = router;
+//! ```
+
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::{header, HeaderName, HeaderValue, StatusCode};
+use axum::middleware::{from_fn, from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use rand::Rng;
+use serde_json::json;
+use tokio::sync::Semaphore;
+use tower_http::catch_panic::CatchPanicLayer;
+use tracing_ext::{
+    record_exception_on_active_span, set_attribute_on_active_span, AttributeVisibility,
+    TraceableStdError,
+};
+
+/// Header carrying the request ID.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Default time given to a request before answering `504 Gateway Timeout`.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default maximum size of a request body in bytes.
+pub const DEFAULT_BODY_LIMIT: usize = 1024 * 1024;
+
+/// Default maximum number of requests handled concurrently.
+pub const DEFAULT_CONCURRENCY_LIMIT: usize = 1024;
+
+/// Default delay the shed requests are asked to retry after.
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Maximum length of a request ID taken from the request.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// The ID of a request, available as a request extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+/// The middleware stack of a production server, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct MiddlewareStack {
+    request_timeout: Duration,
+    body_limit: usize,
+    concurrency_limit: usize,
+    retry_after: Duration,
+}
+
+impl Default for MiddlewareStack {
+    fn default() -> Self {
+        Self {
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            body_limit: DEFAULT_BODY_LIMIT,
+            concurrency_limit: DEFAULT_CONCURRENCY_LIMIT,
+            retry_after: DEFAULT_RETRY_AFTER,
+        }
+    }
+}
+
+impl MiddlewareStack {
+    /// Creates a stack with the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time given to a request before answering `504 Gateway Timeout`.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Sets the maximum size of a request body in bytes.
+    pub fn with_body_limit(mut self, body_limit: usize) -> Self {
+        self.body_limit = body_limit;
+        self
+    }
+
+    /// Sets the maximum number of requests handled concurrently.
+    pub fn with_concurrency_limit(mut self, concurrency_limit: usize) -> Self {
+        self.concurrency_limit = concurrency_limit;
+        self
+    }
+
+    /// Sets the delay the shed requests are asked to retry after, rounded up to the second.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Layers the routes of `router` with the stack.
+    pub fn apply<S>(self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let load_shedding = Arc::new(LoadShedding {
+            permits: Semaphore::new(self.concurrency_limit),
+            retry_after: HeaderValue::from(self.retry_after.as_secs_f64().ceil() as u64),
+        });
+        // The last layer added is the outermost.
+        router
+            .layer(CatchPanicLayer::custom(panic_response))
+            .layer(DefaultBodyLimit::max(self.body_limit))
+            .layer(from_fn_with_state(self.body_limit, limit_body))
+            .layer(from_fn_with_state(self.request_timeout, timeout))
+            .layer(from_fn_with_state(load_shedding, shed_load))
+            .layer(from_fn(request_id))
+    }
+}
+
+/// The JSON error answered by the stack.
+fn error_response(status_code: StatusCode, message: impl Into<String>) -> Response {
+    let body = Json(json!({
+        "success": false,
+        "code": status_code.as_u16(),
+        "error": status_code.canonical_reason().unwrap_or("Unknown"),
+        "message": message.into(),
+    }));
+    (status_code, body).into_response()
+}
+
+/// Whether `id` can be used as a request ID without being logged or echoed unsafely.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+}
+
+/// A random request ID of 32 hexadecimal digits.
+fn generate_request_id() -> String {
+    format!("{:032x}", rand::thread_rng().gen::<u128>())
+}
+
+async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    // The ID is made of visible ASCII characters only.
+    let header_value = HeaderValue::from_str(&id).expect("valid request ID header value");
+    set_attribute_on_active_span(AttributeVisibility::Default, "http.request.id", id.clone());
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value.clone());
+    request.extensions_mut().insert(RequestId(id));
+
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value);
+    response
+}
+
+struct LoadShedding {
+    permits: Semaphore,
+    retry_after: HeaderValue,
+}
+
+async fn shed_load(
+    State(load_shedding): State<Arc<LoadShedding>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(_permit) = load_shedding.permits.try_acquire() else {
+        let mut response = error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many concurrent requests",
+        );
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, load_shedding.retry_after.clone());
+        return response;
+    };
+    next.run(request).await
+}
+
+async fn timeout(State(timeout): State<Duration>, request: Request, next: Next) -> Response {
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => error_response(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("request timed out after {}ms", timeout.as_millis()),
+        ),
+    }
+}
+
+/// Rejects the requests announcing a body above the limit, the others are limited by
+/// [`DefaultBodyLimit`] when extracting the body.
+async fn limit_body(State(body_limit): State<usize>, request: Request, next: Next) -> Response {
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    match content_length {
+        Some(length) if length > body_limit as u64 => error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("request body exceeds {body_limit} bytes"),
+        ),
+        _ => next.run(request).await,
+    }
+}
+
+/// A panic of a request handler.
+#[derive(Debug, thiserror::Error)]
+#[error("request handler panicked: {0}")]
+struct HandlerPanic(String);
+
+fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    };
+    // The panic message may reveal internals, so it is only recorded on the span.
+    record_exception_on_active_span(&TraceableStdError::internal(HandlerPanic(message)));
+    error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::routing::post;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn panicking() -> &'static str {
+        panic!("boom")
+    }
+
+    fn router(stack: MiddlewareStack, release: Arc<Notify>) -> Router {
+        let router = Router::new()
+            .route("/ok", post(|| async { "ok" }))
+            .route("/body", post(|body: String| async move { body }))
+            .route("/panic", post(panicking))
+            .route(
+                "/wait",
+                post(move || async move {
+                    release.notified().await;
+                }),
+            );
+        stack.apply(router)
+    }
+
+    fn request(path: &str, body: &'static str) -> Request {
+        Request::post(path).body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let router = router(MiddlewareStack::new(), Arc::default());
+
+        let response = router.clone().oneshot(request("/ok", "")).await.unwrap();
+        let id = response.headers()[&REQUEST_ID_HEADER].to_str().unwrap();
+        assert_eq!(id.len(), 32);
+
+        let mut propagated = request("/ok", "");
+        propagated
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc-123"));
+        let response = router.oneshot(propagated).await.unwrap();
+        assert_eq!(response.headers()[&REQUEST_ID_HEADER], "abc-123");
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let release = Arc::new(Notify::new());
+        let stack = MiddlewareStack::new()
+            .with_body_limit(4)
+            .with_request_timeout(Duration::from_millis(50))
+            .with_concurrency_limit(1);
+        let router = router(stack, release.clone());
+
+        let response = router
+            .clone()
+            .oneshot(request("/body", "too large"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = router.clone().oneshot(request("/wait", "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let waiting = tokio::spawn(router.clone().oneshot(request("/wait", "")));
+        tokio::task::yield_now().await;
+        let response = router.clone().oneshot(request("/ok", "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        assert!(response.headers().contains_key(&REQUEST_ID_HEADER));
+        release.notify_waiters();
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_panic() {
+        let exporter = tracing_ext::testing::InMemorySpanExporter::global();
+        let router = router(MiddlewareStack::new(), Arc::default())
+            .layer(from_fn(tracing_ext::graphql_request_tracing_middleware));
+
+        let response = router.oneshot(request("/panic", "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let id = response.headers()[&REQUEST_ID_HEADER].to_str().unwrap();
+        exporter
+            .assert_span("POST /panic")
+            .has_error()
+            .has_event("exception")
+            .has_attribute("http.request.id", id.to_string());
+    }
+}
@@ -3,31 +3,32 @@
 //! The code is adapted from the `axum-server` crate:
 //! <https://github.com/tokio-rs/axum/blob/main/examples/graceful-shutdown/src/main.rs>
 
-use tokio::signal;
+use tracing::error;
 
-/// Waits for a shutdown signal.
-pub async fn shutdown_signal() {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
-
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
-    };
+use crate::signal::{first_signal, Signal};
 
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
+/// The signals [`shutdown_signal`] waits for: Ctrl-C and, on Unix, `SIGTERM`.
+#[cfg(unix)]
+pub const DEFAULT_SHUTDOWN_SIGNALS: &[Signal] = &[Signal::Interrupt, Signal::Terminate];
+/// The signals [`shutdown_signal`] waits for: Ctrl-C and, on Unix, `SIGTERM`.
+#[cfg(not(unix))]
+pub const DEFAULT_SHUTDOWN_SIGNALS: &[Signal] = &[Signal::Interrupt];
 
-    tokio::select! {
-            _ = ctrl_c => {},
-            _ = terminate => {},
-    }
+/// Waits for a shutdown signal.
+///
+/// A signal whose handler fails to install is logged and not waited for, in which case its default
+/// action, terminating the process, still applies. Use [`shutdown_signals`](crate::shutdown_signals) to handle the failure.
+pub async fn shutdown_signal() {
+    let listeners = DEFAULT_SHUTDOWN_SIGNALS
+        .iter()
+        .filter_map(|signal| {
+            signal
+                .listen()
+                .map_err(|error| error!(%error, "shutdown signal not handled"))
+                .ok()
+        })
+        .collect();
+    first_signal(listeners).await;
 }
 
 /// Waits for a shutdown signal and then invokes a handler.
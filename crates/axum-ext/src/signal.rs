@@ -0,0 +1,303 @@
+//! This module provides non-panicking handling of the process signals.
+//!
+//! Installing a signal handler fails e.g. when the runtime has no signal driver. The functions of
+//! this module return a [`SignalError`] instead of panicking, before waiting for any signal, so
+//! that the failure surfaces at startup.
+//!
+//! [`SignalHandlers`] runs an async handler every time a signal is received, e.g. reloading on
+//! `SIGHUP` or toggling the log level on `SIGUSR1`, while [`shutdown_signals`] waits for the first
+//! of a set of signals to shut down.
+//!
+//! # Example:
+//! ```no_run
+//! use axum_ext::{shutdown_signals, Signal, SignalHandlers};
+//! use tokio_util::sync::CancellationToken;
+//!
+//! # async fn example() -> Result<(), axum_ext::SignalError> {
+//! let token = CancellationToken::new();
+//! let handlers = SignalHandlers::new()
+//!     .on(Signal::Hangup, || async { /* reload the configuration */ })
+//!     .on(Signal::User2, || async { /* dump the cache */ })
+//!     .listen(token.clone())?;
+//! tokio::spawn(handlers);
+//!
+//! let received = shutdown_signals(&[Signal::Interrupt, Signal::Terminate])?.await;
+//! token.cancel();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// A signal sent to the process.
+///
+/// Only [`Signal::Interrupt`] (Ctrl-C) is supported on non-Unix platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    /// `SIGINT`, sent by Ctrl-C
+    Interrupt,
+    /// `SIGTERM`, sent to stop the process, e.g. by Kubernetes
+    Terminate,
+    /// `SIGHUP`, conventionally asking to reload the configuration
+    Hangup,
+    /// `SIGUSR1`, with an application-defined meaning
+    User1,
+    /// `SIGUSR2`, with an application-defined meaning
+    User2,
+    /// `SIGQUIT`, sent by Ctrl-\
+    Quit,
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Interrupt => "SIGINT",
+            Self::Terminate => "SIGTERM",
+            Self::Hangup => "SIGHUP",
+            Self::User1 => "SIGUSR1",
+            Self::User2 => "SIGUSR2",
+            Self::Quit => "SIGQUIT",
+        })
+    }
+}
+
+/// The failure to install the handler of a signal.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to install the {signal} handler: {source}")]
+pub struct SignalError {
+    pub signal: Signal,
+    #[source]
+    pub source: io::Error,
+}
+
+impl Signal {
+    /// Installs a handler of the signal, returning the stream of its deliveries.
+    ///
+    /// The default action of the signal, e.g. terminating the process, no longer applies once
+    /// installed.
+    pub fn listen(self) -> Result<SignalListener, SignalError> {
+        self.install()
+            .map(|inner| SignalListener {
+                signal: self,
+                inner,
+            })
+            .map_err(|source| SignalError {
+                signal: self,
+                source,
+            })
+    }
+
+    #[cfg(unix)]
+    fn install(self) -> io::Result<tokio::signal::unix::Signal> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        signal(match self {
+            Self::Interrupt => SignalKind::interrupt(),
+            Self::Terminate => SignalKind::terminate(),
+            Self::Hangup => SignalKind::hangup(),
+            Self::User1 => SignalKind::user_defined1(),
+            Self::User2 => SignalKind::user_defined2(),
+            Self::Quit => SignalKind::quit(),
+        })
+    }
+
+    #[cfg(windows)]
+    fn install(self) -> io::Result<tokio::signal::windows::CtrlC> {
+        match self {
+            Self::Interrupt => tokio::signal::windows::ctrl_c(),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only Ctrl-C is supported on this platform",
+            )),
+        }
+    }
+}
+
+/// The stream of the deliveries of a signal, see [`Signal::listen`].
+#[derive(Debug)]
+pub struct SignalListener {
+    signal: Signal,
+    #[cfg(unix)]
+    inner: tokio::signal::unix::Signal,
+    #[cfg(windows)]
+    inner: tokio::signal::windows::CtrlC,
+}
+
+impl SignalListener {
+    /// The signal listened to.
+    pub fn signal(&self) -> Signal {
+        self.signal
+    }
+
+    /// Waits for the next delivery of the signal, returning `None` if no more can be received.
+    pub async fn recv(&mut self) -> Option<()> {
+        self.inner.recv().await
+    }
+}
+
+/// Waits for the first of the `signals`, returning which one was received.
+///
+/// The handlers of all the `signals` are installed before returning, so that a failure surfaces
+/// immediately rather than when awaiting.
+pub fn shutdown_signals(
+    signals: &[Signal],
+) -> Result<impl Future<Output = Signal> + Send + 'static, SignalError> {
+    let listeners = signals
+        .iter()
+        .map(|signal| signal.listen())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(first_signal(listeners))
+}
+
+/// Waits for the first signal received by the `listeners`, forever if there are none.
+pub(crate) async fn first_signal(listeners: Vec<SignalListener>) -> Signal {
+    let mut received = JoinSet::new();
+    for mut listener in listeners {
+        received.spawn(async move {
+            listener.recv().await?;
+            Some(listener.signal())
+        });
+    }
+    while let Some(signal) = received.join_next().await {
+        if let Ok(Some(signal)) = signal {
+            return signal;
+        }
+    }
+    // None of the signals can be received anymore.
+    std::future::pending().await
+}
+
+type SignalHandler = Box<dyn FnMut() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// Async handlers run every time a signal is received.
+#[derive(Default)]
+pub struct SignalHandlers {
+    handlers: Vec<(Signal, SignalHandler)>,
+}
+
+impl fmt::Debug for SignalHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignalHandlers")
+            .field(
+                "signals",
+                &self
+                    .handlers
+                    .iter()
+                    .map(|(signal, _)| signal)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl SignalHandlers {
+    /// Creates an empty set of handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `handler` every time `signal` is received.
+    ///
+    /// The deliveries of a signal received while its handler runs are coalesced into one.
+    pub fn on<F, Fut>(mut self, signal: Signal, mut handler: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.handlers
+            .push((signal, Box::new(move || Box::pin(handler()))));
+        self
+    }
+
+    /// Installs the handlers, returning the future running them until `cancellation_token` is
+    /// cancelled, e.g. to spawn with [`Lifecycle::spawn`](crate::Lifecycle::spawn).
+    pub fn listen(
+        self,
+        cancellation_token: CancellationToken,
+    ) -> Result<impl Future<Output = ()> + Send + 'static, SignalError> {
+        let handlers = self
+            .handlers
+            .into_iter()
+            .map(|(signal, handler)| Ok((signal.listen()?, handler)))
+            .collect::<Result<Vec<_>, SignalError>>()?;
+        Ok(async move {
+            let mut running = JoinSet::new();
+            for (mut listener, mut handler) in handlers {
+                let cancellation_token = cancellation_token.clone();
+                running.spawn(async move {
+                    loop {
+                        tokio::select! {
+                            _ = cancellation_token.cancelled() => break,
+                            received = listener.recv() => match received {
+                                Some(()) => handler().await,
+                                None => break,
+                            },
+                        }
+                    }
+                });
+            }
+            while running.join_next().await.is_some() {}
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Sends `signal` to the current process.
+    fn raise(signal: &str) {
+        let status = std::process::Command::new("kill")
+            .args([&format!("-{signal}"), &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn test_signal_handlers() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let token = CancellationToken::new();
+        let handlers = SignalHandlers::new()
+            .on(Signal::User2, move || {
+                handler_calls.fetch_add(1, Ordering::SeqCst);
+                std::future::ready(())
+            })
+            .listen(token.clone())
+            .unwrap();
+        let handlers = tokio::spawn(handlers);
+
+        raise("USR2");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while calls.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        token.cancel();
+        handlers.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_signals() {
+        let shutdown = shutdown_signals(&[Signal::Terminate, Signal::Hangup]).unwrap();
+        raise("HUP");
+        let received = tokio::time::timeout(Duration::from_secs(5), shutdown)
+            .await
+            .unwrap();
+        assert_eq!(received, Signal::Hangup);
+    }
+}
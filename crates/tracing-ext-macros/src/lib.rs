@@ -0,0 +1,128 @@
+//! Procedural macros for `tracing-ext`.
+//!
+//! This crate is re-exported by `tracing-ext` and should be used through it, as the generated code
+//! refers to `::tracing_ext`.
+
+use proc_macro::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_macro_input, ItemFn, LitStr};
+
+/// Runs the annotated function in a span of the global `tracing-ext` tracer.
+///
+/// The function must return a value implementing `Traceable`, which sets the error status of the
+/// span. Both sync and async functions are supported: sync functions are run with
+/// `Tracer::in_span`, async ones with `Tracer::in_span_async`. As the latter boxes the body of the
+/// function into a `Send` future, the arguments and the values held across `.await` in async
+/// functions must be `Send`.
+///
+/// # Arguments
+///
+/// * `name` - Name of the span, defaults to the name of the function
+/// * `display_name` - Display name of the span, defaults to `name`
+/// * `visibility` - `"internal"` (the default) or `"user"`
+///
+/// # Example:
+/// ```
+/// use tracing_ext::{traced, Successful};
+///
+/// struct Profile;
+///
+/// #[traced(name = "get_profile", visibility = "internal")]
+/// async fn get_profile(api_key: String) -> Result<Profile, anyhow::Error> {
+///     anyhow::ensure!(!api_key.is_empty(), "the API key is required");
+///     Ok(Profile)
+/// }
+///
+/// #[traced]
+/// fn parse(input: &str) -> Successful<usize> {
+///     Successful::new(input.len())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn traced(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args_builder = TracedArgs::default();
+    let parser = syn::meta::parser(|meta| args_builder.parse(meta));
+    parse_macro_input!(args with parser);
+    let function = parse_macro_input!(item as ItemFn);
+
+    match expand(args_builder, function) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct TracedArgs {
+    name: Option<LitStr>,
+    display_name: Option<LitStr>,
+    visibility: Option<LitStr>,
+}
+
+impl TracedArgs {
+    fn parse(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("name") {
+            self.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("display_name") {
+            self.display_name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("visibility") {
+            self.visibility = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error(
+                "unsupported traced argument, expected `name`, `display_name` or `visibility`",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn expand(args: TracedArgs, function: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = function;
+
+    let name = args
+        .name
+        .unwrap_or_else(|| LitStr::new(&sig.ident.to_string(), sig.ident.span()));
+    let display_name = args.display_name.unwrap_or_else(|| name.clone());
+    let visibility = match &args.visibility {
+        None => quote!(::tracing_ext::SpanVisibility::Internal),
+        Some(visibility) => match visibility.value().as_str() {
+            "internal" => quote!(::tracing_ext::SpanVisibility::Internal),
+            "user" => quote!(::tracing_ext::SpanVisibility::User),
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    visibility,
+                    "expected `\"internal\"` or `\"user\"`",
+                ))
+            }
+        },
+    };
+
+    let body = if sig.asyncness.is_some() {
+        quote! {
+            ::tracing_ext::global_tracer()
+                .in_span_async(#name, #display_name, #visibility, || {
+                    ::std::boxed::Box::pin(async move #block)
+                })
+                .await
+        }
+    } else {
+        quote! {
+            ::tracing_ext::global_tracer().in_span(#name, #display_name, #visibility, || #block)
+        }
+    };
+
+    let mut tokens = proc_macro2::TokenStream::new();
+    for attr in attrs {
+        attr.to_tokens(&mut tokens);
+    }
+    tokens.extend(quote! {
+        #vis #sig {
+            #body
+        }
+    });
+    Ok(tokens)
+}
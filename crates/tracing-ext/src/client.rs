@@ -0,0 +1,383 @@
+//! Trace-instrumented outbound HTTP client
+//!
+//! This module contains a wrapper around [`reqwest::Client`] which opens a [`SpanKind::Client`]
+//! span for every request, records the HTTP semantic-convention attributes on it, and injects the
+//! propagation headers of that span into the request.
+//!
+//! # Example:
+//! ```no_run
+//! use tracing_ext::TracedHttpClient;
+//!
+//! # async fn fetch() -> Result<(), reqwest::Error> {
+//! let client = TracedHttpClient::new(reqwest::Client::new());
+//! let request = client.client().get("http://kong:8001/status").build()?;
+//! let response = client.execute(request).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use opentelemetry::trace::{get_active_span, SpanKind};
+use opentelemetry::KeyValue;
+use opentelemetry_semantic_conventions as semcov;
+
+use crate::request::get_trace_headers;
+use crate::traceable::{ErrorVisibility, Traceable, TraceableError};
+use crate::tracer::{global_tracer, SpanVisibility};
+
+/// Wrapper around [`reqwest::Client`] tracing every request in a client span.
+#[derive(Debug, Clone)]
+pub struct TracedHttpClient {
+    client: reqwest::Client,
+    visibility: SpanVisibility,
+}
+
+impl TracedHttpClient {
+    /// Creates a new `TracedHttpClient`. Its spans are internal, as outbound requests are an
+    /// implementation detail of the service.
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            visibility: SpanVisibility::Internal,
+        }
+    }
+
+    /// Sets the visibility of the spans opened for the requests.
+    pub fn with_visibility(mut self, visibility: SpanVisibility) -> Self {
+        self.visibility = visibility;
+        self
+    }
+
+    /// The wrapped client, used to build the requests.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Executes the `request` in a client span named after its method, recording the full URL
+    /// without credentials and query.
+    pub async fn execute(
+        &self,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        self.execute_traced(request, None).await
+    }
+
+    /// Executes the `request` in a client span named after its method and `url_template`, e.g.
+    /// `/key-auths/{key}/consumer`. The template is recorded instead of the full URL, so that
+    /// identifiers and secrets in the path do not end up in the span.
+    pub async fn execute_with_template(
+        &self,
+        request: reqwest::Request,
+        url_template: &'static str,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        self.execute_traced(request, Some(url_template)).await
+    }
+
+    async fn execute_traced(
+        &self,
+        mut request: reqwest::Request,
+        url_template: Option<&'static str>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let method = request.method().as_str().to_string();
+        let name = match url_template {
+            Some(url_template) => format!("{method} {url_template}"),
+            None => method.clone(),
+        };
+        let attributes = request_attributes(&method, request.url(), url_template);
+        let client = self.client.clone();
+
+        let traced = global_tracer()
+            .in_span_async_with_kind(
+                name.clone(),
+                name,
+                self.visibility,
+                SpanKind::Client,
+                attributes,
+                || {
+                    Box::pin(async move {
+                        // The client span is the current span here, so it becomes the parent of
+                        // the downstream spans.
+                        request.headers_mut().extend(get_trace_headers());
+                        let result = client.execute(request).await;
+                        get_active_span(|span| match &result {
+                            Ok(response) => span.set_attribute(KeyValue::new(
+                                semcov::trace::HTTP_RESPONSE_STATUS_CODE,
+                                i64::from(response.status().as_u16()),
+                            )),
+                            Err(error) => span.set_attribute(KeyValue::new(
+                                semcov::trace::ERROR_TYPE,
+                                error_type(error),
+                            )),
+                        });
+                        TracedHttpResult(result, url_template)
+                    })
+                },
+            )
+            .await;
+        traced.0
+    }
+}
+
+fn request_attributes(
+    method: &str,
+    url: &reqwest::Url,
+    url_template: Option<&'static str>,
+) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::new(semcov::trace::HTTP_REQUEST_METHOD, method.to_string()),
+        KeyValue::new(semcov::trace::URL_SCHEME, url.scheme().to_string()),
+    ];
+    if let Some(host) = url.host_str() {
+        attributes.push(KeyValue::new(
+            semcov::trace::SERVER_ADDRESS,
+            host.to_string(),
+        ));
+    }
+    if let Some(port) = url.port_or_known_default() {
+        attributes.push(KeyValue::new(semcov::trace::SERVER_PORT, i64::from(port)));
+    }
+    match url_template {
+        Some(url_template) => {
+            attributes.push(KeyValue::new(semcov::attribute::URL_TEMPLATE, url_template))
+        }
+        None => attributes.push(KeyValue::new(semcov::trace::URL_FULL, redacted_url(url))),
+    }
+    attributes
+}
+
+/// The URL without user info and query, which may both contain credentials.
+fn redacted_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+    url.set_query(None);
+    url.set_fragment(None);
+    url.to_string()
+}
+
+/// Low-cardinality classification of a request error for the `error.type` attribute.
+fn error_type(error: &reqwest::Error) -> &'static str {
+    if error.is_timeout() {
+        "timeout"
+    } else if error.is_connect() {
+        "connect"
+    } else if error.is_request() {
+        "request"
+    } else if error.is_body() || error.is_decode() {
+        "body"
+    } else {
+        "_OTHER"
+    }
+}
+
+/// The result of a traced request.
+/// Only used to implement [`Traceable`] in [`TracedHttpClient`].
+struct TracedHttpResult(
+    Result<reqwest::Response, reqwest::Error>,
+    Option<&'static str>,
+);
+
+/// Error type for [`TracedHttpResult`].
+#[derive(Debug, derive_more::Display)]
+#[display("{description}")]
+pub struct HttpClientError {
+    description: String,
+    details: String,
+}
+
+impl TraceableError for HttpClientError {
+    fn visibility(&self) -> ErrorVisibility {
+        // Failures of outbound requests are internal to the service.
+        ErrorVisibility::Internal
+    }
+
+    fn details(&self) -> String {
+        self.details.clone()
+    }
+}
+
+impl HttpClientError {
+    /// The error of a failed request. The error messages contain the request URL, which is
+    /// redacted like the `url.full` attribute, or replaced by the `url_template`.
+    pub(crate) fn from_error(error: &reqwest::Error, url_template: Option<&'static str>) -> Self {
+        let redact = |message: String| match (error.url(), url_template) {
+            (Some(url), Some(url_template)) => message.replace(url.as_str(), url_template),
+            (Some(url), None) => message.replace(url.as_str(), &redacted_url(url)),
+            (None, _) => message,
+        };
+        Self {
+            description: redact(error.to_string()),
+            details: redact(format!("{error:?}")),
+        }
+    }
+
+    /// The error of a response with a client or server error status, if any.
+    pub(crate) fn from_response(
+        response: &reqwest::Response,
+        url_template: Option<&'static str>,
+    ) -> Option<Self> {
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return None;
+        }
+        let target = match url_template {
+            Some(url_template) => url_template.to_string(),
+            None => redacted_url(response.url()),
+        };
+        Some(Self {
+            description: format!("HTTP request to {target} failed with status {status}"),
+            details: format!("{status:?}"),
+        })
+    }
+}
+
+impl Traceable for TracedHttpResult {
+    type ErrorType<'a> = HttpClientError;
+
+    fn get_error(&self) -> Option<Self::ErrorType<'_>> {
+        match &self.0 {
+            Err(error) => Some(HttpClientError::from_error(error, self.1)),
+            Ok(response) => HttpClientError::from_response(response, self.1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Path;
+    use axum::http::{HeaderMap, StatusCode};
+    use opentelemetry::global;
+    use opentelemetry::trace::Status;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+    use super::*;
+    use crate::testing::InMemorySpanExporter;
+
+    /// Starts a server answering `/status/{code}` with that status, and with the `traceparent`
+    /// header of the request as body. Returns its address.
+    async fn serve() -> std::net::SocketAddr {
+        let router = axum::Router::new().route(
+            "/status/:code",
+            axum::routing::get(|Path(code): Path<u16>, headers: HeaderMap| async move {
+                let traceparent = headers
+                    .get("traceparent")
+                    .map(|value| value.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                (StatusCode::from_u16(code).unwrap(), traceparent)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        address
+    }
+
+    /// A client tracing to the global exporter, which is returned for the assertions.
+    fn traced_client() -> (TracedHttpClient, InMemorySpanExporter) {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        (
+            TracedHttpClient::new(reqwest::Client::new()),
+            InMemorySpanExporter::global(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_execute() {
+        let address = serve().await;
+        let (client, exporter) = traced_client();
+
+        let request = client
+            .client()
+            .get(format!(
+                "http://user:secret@{address}/status/200?token=secret"
+            ))
+            .build()
+            .unwrap();
+        let response = client.execute(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let traceparent = response.text().await.unwrap();
+
+        let assertion = exporter.assert_span("GET");
+        let span_context = &assertion.span().span_context;
+        // The propagated context is the one of the client span.
+        assert_eq!(
+            traceparent,
+            format!(
+                "00-{}-{}-01",
+                span_context.trace_id(),
+                span_context.span_id()
+            )
+        );
+        assertion
+            .has_kind(SpanKind::Client)
+            .has_attribute("internal.visibility", "internal")
+            .has_attribute(semcov::trace::HTTP_REQUEST_METHOD, "GET")
+            .has_attribute(semcov::trace::URL_SCHEME, "http")
+            .has_attribute(semcov::trace::SERVER_ADDRESS, "127.0.0.1")
+            .has_attribute(semcov::trace::SERVER_PORT, i64::from(address.port()))
+            .has_attribute(
+                semcov::trace::URL_FULL,
+                format!("http://{address}/status/200"),
+            )
+            .has_attribute(semcov::trace::HTTP_RESPONSE_STATUS_CODE, 200)
+            .is_ok();
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_template_error_status() {
+        let address = serve().await;
+        let (client, exporter) = traced_client();
+
+        for code in [404, 500] {
+            let request = client
+                .client()
+                .get(format!("http://{address}/status/{code}"))
+                .build()
+                .unwrap();
+            let response = client
+                .execute_with_template(request, "/status/{code}")
+                .await
+                .unwrap();
+            let status = response.status();
+
+            exporter
+                .assert_span("GET /status/{code}")
+                .has_attribute(semcov::attribute::URL_TEMPLATE, "/status/{code}")
+                .has_no_attribute(semcov::trace::URL_FULL)
+                .has_attribute(semcov::trace::HTTP_RESPONSE_STATUS_CODE, i64::from(code))
+                .has_error_description(&format!(
+                    "HTTP request to /status/{{code}} failed with status {status}"
+                ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_transport_error() {
+        // A port nothing listens on anymore.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let (client, exporter) = traced_client();
+
+        let request = client
+            .client()
+            .get(format!("http://{address}/unreachable/secret"))
+            .build()
+            .unwrap();
+        let result = client
+            .execute_with_template(request, "/unreachable/{id}")
+            .await;
+        assert!(result.unwrap_err().is_connect());
+
+        let assertion = exporter.assert_span("GET /unreachable/{id}");
+        assertion
+            .has_attribute(semcov::trace::ERROR_TYPE, "connect")
+            .has_no_attribute(semcov::trace::HTTP_RESPONSE_STATUS_CODE)
+            .has_error();
+        // The URL is replaced by its template in the error messages.
+        let Status::Error { description } = &assertion.span().status else {
+            unreachable!()
+        };
+        assert!(description.contains("/unreachable/{id}"), "{description}");
+        assert!(!description.contains("secret"), "{description}");
+    }
+}
@@ -0,0 +1,569 @@
+//! Span exporter configuration.
+//!
+//! Spans are exported either to an OTLP collector or to a Zipkin collector, as selected by
+//! [`SpanExporterConfig`].
+//!
+//! The OTLP span exporter is built from an [`OtlpExporterConfig`], supporting:
+//! - gRPC, HTTP/protobuf and HTTP/JSON transports
+//! - Custom headers (e.g. API keys for hosted collectors)
+//! - TLS with a custom CA certificate
+//! - gzip and zstd compression
+//! - Export timeouts
+//!
+//! `opentelemetry-otlp` also reads some of the standard `OTEL_EXPORTER_OTLP_*` environment
+//! variables itself, applied on top of the values configured here:
+//! - `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, then `OTEL_EXPORTER_OTLP_ENDPOINT`, replace the endpoint
+//! - `OTEL_EXPORTER_OTLP_HEADERS` headers are added to the configured ones, replacing those with
+//!   the same name
+//! - `OTEL_EXPORTER_OTLP_TIMEOUT` and `OTEL_EXPORTER_OTLP_COMPRESSION` replace the timeout and
+//!   compression of the gRPC transport only; HTTP exports always use the configured ones
+//!
+//! Applications reading these variables (like the auth-webhook CLI) should map them into an
+//! [`OtlpExporterConfig`], so they end up with the same values either way.
+//!
+//! The OTLP metric exporter of [`crate::init_meter_provider`] is built from the same
+//! [`OtlpExporterConfig`], sending to the same collector.
+//!
+//! The Zipkin span exporter is built from a [`ZipkinExporterConfig`] and posts spans in the
+//! Zipkin v2 JSON format.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression as GzipLevel};
+use opentelemetry::trace::TraceError;
+use opentelemetry_http::{HttpClient, HttpError, Request, Response};
+pub use opentelemetry_otlp::Compression;
+use opentelemetry_otlp::{Protocol, WithExportConfig, WithHttpConfig, WithTonicConfig};
+use opentelemetry_sdk::metrics::MetricError;
+use opentelemetry_sdk::runtime::Tokio;
+use opentelemetry_sdk::trace::BatchSpanProcessor;
+use percent_encoding::percent_decode_str;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Certificate, ClientTlsConfig};
+
+/// Paths appended to HTTP endpoints given without one, as required by the OTLP specification.
+const OTLP_HTTP_TRACES_PATH: &str = "/v1/traces";
+const OTLP_HTTP_METRICS_PATH: &str = "/v1/metrics";
+
+/// Default timeout of a single export request.
+const DEFAULT_EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The span exporter spans are sent to.
+#[derive(Debug, Clone)]
+pub enum SpanExporterConfig {
+    /// Export spans to an OTLP collector.
+    Otlp(OtlpExporterConfig),
+    /// Export spans to a Zipkin collector.
+    Zipkin(ZipkinExporterConfig),
+}
+
+impl SpanExporterConfig {
+    /// Builds a batch span processor exporting spans with the configured exporter.
+    pub(crate) fn build_span_processor(
+        &self,
+        service_name: &'static str,
+    ) -> Result<BatchSpanProcessor<Tokio>, TraceError> {
+        let processor = match self {
+            SpanExporterConfig::Otlp(config) => {
+                BatchSpanProcessor::builder(config.build_span_exporter()?, Tokio).build()
+            }
+            SpanExporterConfig::Zipkin(config) => {
+                BatchSpanProcessor::builder(config.build_span_exporter(service_name)?, Tokio)
+                    .build()
+            }
+        };
+        Ok(processor)
+    }
+}
+
+/// The transport protocol used to send spans to the OTLP collector.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    /// OTLP over gRPC (usually on port 4317)
+    #[default]
+    Grpc,
+    /// OTLP over HTTP with protobuf payloads (usually on port 4318)
+    HttpProtobuf,
+    /// OTLP over HTTP with JSON payloads (usually on port 4318)
+    HttpJson,
+}
+
+/// Parses the values of `OTEL_EXPORTER_OTLP_PROTOCOL`: `grpc`, `http/protobuf` and `http/json`.
+impl FromStr for OtlpProtocol {
+    type Err = TraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grpc" => Ok(OtlpProtocol::Grpc),
+            "http/protobuf" => Ok(OtlpProtocol::HttpProtobuf),
+            "http/json" => Ok(OtlpProtocol::HttpJson),
+            _ => Err(format!("unsupported OTLP protocol: {s}").into()),
+        }
+    }
+}
+
+/// Configuration of the OTLP span exporter.
+///
+/// # Example:
+/// ```
+/// use std::time::Duration;
+/// use tracing_ext::{OtlpCompression, OtlpExporterConfig, OtlpProtocol};
+///
+/// let config = OtlpExporterConfig::new("https://otlp.example.com")
+///     .with_protocol(OtlpProtocol::HttpProtobuf)
+///     .with_headers(tracing_ext::parse_otlp_headers("x-api-key=secret"))
+///     .with_compression(Some(OtlpCompression::Gzip))
+///     .with_timeout(Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct OtlpExporterConfig {
+    /// The collector endpoint (e.g. "http://localhost:4317").
+    pub endpoint: String,
+    /// The transport protocol.
+    pub protocol: OtlpProtocol,
+    /// Additional headers sent with every export request.
+    pub headers: HashMap<String, String>,
+    /// Compression of the export requests.
+    pub compression: Option<Compression>,
+    /// Path to a PEM encoded CA certificate used to verify the collector's TLS certificate.
+    pub ca_certificate: Option<PathBuf>,
+    /// Timeout of a single export request.
+    pub timeout: Duration,
+}
+
+impl OtlpExporterConfig {
+    /// Creates a new gRPC `OtlpExporterConfig` for the given `endpoint`.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            protocol: OtlpProtocol::default(),
+            headers: HashMap::new(),
+            compression: None,
+            ca_certificate: None,
+            timeout: DEFAULT_EXPORT_TIMEOUT,
+        }
+    }
+
+    /// Sets the transport protocol.
+    pub fn with_protocol(mut self, protocol: OtlpProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Sets the additional headers sent with every export request.
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Sets the compression of the export requests.
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the path to a PEM encoded CA certificate.
+    pub fn with_ca_certificate(mut self, ca_certificate: Option<PathBuf>) -> Self {
+        self.ca_certificate = ca_certificate;
+        self
+    }
+
+    /// Sets the timeout of a single export request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Builds the OTLP span exporter described by this configuration.
+    pub(crate) fn build_span_exporter(
+        &self,
+    ) -> Result<opentelemetry_otlp::SpanExporter, TraceError> {
+        match self.protocol {
+            OtlpProtocol::Grpc => self.build_grpc_span_exporter(),
+            OtlpProtocol::HttpProtobuf => self.build_http_span_exporter(Protocol::HttpBinary),
+            OtlpProtocol::HttpJson => self.build_http_span_exporter(Protocol::HttpJson),
+        }
+    }
+
+    fn build_grpc_span_exporter(&self) -> Result<opentelemetry_otlp::SpanExporter, TraceError> {
+        self.with_tonic_config(opentelemetry_otlp::SpanExporter::builder().with_tonic())?
+            .build()
+    }
+
+    fn build_http_span_exporter(
+        &self,
+        protocol: Protocol,
+    ) -> Result<opentelemetry_otlp::SpanExporter, TraceError> {
+        let builder = opentelemetry_otlp::SpanExporter::builder().with_http();
+        self.with_http_config(builder, protocol, OTLP_HTTP_TRACES_PATH)?
+            .build()
+    }
+
+    /// Builds the OTLP metric exporter sending to the same collector as the spans.
+    pub(crate) fn build_metric_exporter(
+        &self,
+    ) -> Result<opentelemetry_otlp::MetricExporter, MetricError> {
+        let to_metric_error = |error: TraceError| MetricError::Other(error.to_string());
+        match self.protocol {
+            OtlpProtocol::Grpc => self
+                .with_tonic_config(opentelemetry_otlp::MetricExporter::builder().with_tonic())
+                .map_err(to_metric_error)?
+                .build(),
+            OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => {
+                let protocol = match self.protocol {
+                    OtlpProtocol::HttpJson => Protocol::HttpJson,
+                    _ => Protocol::HttpBinary,
+                };
+                let builder = opentelemetry_otlp::MetricExporter::builder().with_http();
+                self.with_http_config(builder, protocol, OTLP_HTTP_METRICS_PATH)
+                    .map_err(to_metric_error)?
+                    .build()
+            }
+        }
+    }
+
+    /// Configures the endpoint, timeout, headers, compression and TLS of a gRPC exporter.
+    fn with_tonic_config<B>(&self, builder: B) -> Result<B, TraceError>
+    where
+        B: WithExportConfig + WithTonicConfig,
+    {
+        let mut metadata = MetadataMap::new();
+        for (key, value) in &self.headers {
+            let key = MetadataKey::from_str(key)
+                .map_err(|_| TraceError::from(format!("invalid OTLP header name: {key}")))?;
+            let value = MetadataValue::from_str(value)
+                .map_err(|_| TraceError::from(format!("invalid OTLP header value for {key}")))?;
+            metadata.insert(key, value);
+        }
+
+        let mut builder = builder
+            .with_endpoint(&self.endpoint)
+            .with_timeout(self.timeout)
+            .with_metadata(metadata);
+        if let Some(compression) = self.compression {
+            builder = builder.with_compression(compression);
+        }
+        if self.endpoint.starts_with("https://") || self.ca_certificate.is_some() {
+            let mut tls_config = ClientTlsConfig::new().with_enabled_roots();
+            if let Some(pem) = self.read_ca_certificate()? {
+                tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
+            }
+            builder = builder.with_tls_config(tls_config);
+        }
+        Ok(builder)
+    }
+
+    /// Configures the endpoint, protocol, timeout, headers, compression and TLS of an HTTP
+    /// exporter, appending the `signal_path` to an endpoint given without path.
+    fn with_http_config<B>(
+        &self,
+        builder: B,
+        protocol: Protocol,
+        signal_path: &str,
+    ) -> Result<B, TraceError>
+    where
+        B: WithExportConfig + WithHttpConfig,
+    {
+        // `opentelemetry-otlp` doesn't apply the timeout to custom HTTP clients.
+        let mut client = reqwest::Client::builder().timeout(self.timeout);
+        if let Some(pem) = self.read_ca_certificate()? {
+            let certificate =
+                reqwest::Certificate::from_pem(&pem).map_err(|e| TraceError::Other(e.into()))?;
+            client = client.add_root_certificate(certificate);
+        }
+        let client = client.build().map_err(|e| TraceError::Other(e.into()))?;
+
+        let builder = builder
+            .with_endpoint(http_signal_endpoint(&self.endpoint, signal_path))
+            .with_protocol(protocol)
+            .with_timeout(self.timeout)
+            .with_headers(self.headers.clone());
+        match self.compression {
+            None => Ok(builder.with_http_client(client)),
+            Some(Compression::Gzip) => Ok(builder.with_http_client(GzipHttpClient(client))),
+            Some(Compression::Zstd) => {
+                Err("zstd compression is only supported with the grpc OTLP protocol".into())
+            }
+        }
+    }
+
+    fn read_ca_certificate(&self) -> Result<Option<Vec<u8>>, TraceError> {
+        self.ca_certificate
+            .as_ref()
+            .map(|path| {
+                std::fs::read(path).map_err(|e| {
+                    TraceError::from(format!(
+                        "failed to read OTLP CA certificate {}: {e}",
+                        path.display()
+                    ))
+                })
+            })
+            .transpose()
+    }
+}
+
+/// Configuration of the Zipkin span exporter.
+///
+/// # Example:
+/// ```
+/// use std::time::Duration;
+/// use tracing_ext::ZipkinExporterConfig;
+///
+/// let config = ZipkinExporterConfig::new("http://localhost:9411/api/v2/spans")
+///     .with_timeout(Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ZipkinExporterConfig {
+    /// The collector endpoint spans are posted to (e.g. "http://localhost:9411/api/v2/spans").
+    pub endpoint: String,
+    /// Timeout of a single export request.
+    pub timeout: Duration,
+}
+
+impl ZipkinExporterConfig {
+    /// Creates a new `ZipkinExporterConfig` for the given `endpoint`.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            timeout: DEFAULT_EXPORT_TIMEOUT,
+        }
+    }
+
+    /// Sets the timeout of a single export request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Builds the Zipkin span exporter described by this configuration.
+    pub(crate) fn build_span_exporter(
+        &self,
+        service_name: &'static str,
+    ) -> Result<opentelemetry_zipkin::Exporter, TraceError> {
+        let client = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| TraceError::Other(e.into()))?;
+        opentelemetry_zipkin::new_pipeline()
+            .with_service_name(service_name)
+            .with_collector_endpoint(&self.endpoint)
+            .with_http_client(client)
+            .init_exporter()
+    }
+}
+
+/// Parses headers in the `OTEL_EXPORTER_OTLP_HEADERS` format, i.e. `key1=value1,key2=value2` with
+/// percent-encoded keys and values. Entries without a `=` or not valid UTF-8 once decoded are
+/// ignored.
+pub fn parse_otlp_headers(headers: &str) -> HashMap<String, String> {
+    let decode = |encoded: &str| {
+        percent_decode_str(encoded.trim())
+            .decode_utf8()
+            .ok()
+            .map(|decoded| decoded.into_owned())
+    };
+    headers
+        .split(',')
+        .filter_map(|entry| {
+            let (key, value) = entry.split_once('=')?;
+            Some((decode(key)?, decode(value)?))
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// Appends the OTLP path of a signal to an HTTP endpoint given without a path, so both
+/// `http://localhost:4318` and `http://localhost:4318/v1/traces` can be configured. The traces path
+/// of a configured endpoint is replaced by the path of the other signals.
+fn http_signal_endpoint(endpoint: &str, signal_path: &str) -> String {
+    match http::Uri::from_str(endpoint) {
+        Ok(uri) if uri.path() == "/" && uri.query().is_none() => {
+            format!("{}{signal_path}", endpoint.trim_end_matches('/'))
+        }
+        _ => match endpoint.strip_suffix(OTLP_HTTP_TRACES_PATH) {
+            Some(base) => format!("{base}{signal_path}"),
+            None => endpoint.to_string(),
+        },
+    }
+}
+
+/// An HTTP client compressing the export requests with gzip, which `opentelemetry-otlp` only
+/// supports for gRPC.
+#[derive(Debug)]
+struct GzipHttpClient(reqwest::Client);
+
+#[async_trait]
+impl HttpClient for GzipHttpClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let (mut parts, body) = request.into_parts();
+        let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+        encoder.write_all(&body)?;
+        let body = encoder.finish()?;
+        parts.headers.insert(
+            http::header::CONTENT_ENCODING,
+            http::HeaderValue::from_static("gzip"),
+        );
+        self.0.send(Request::from_parts(parts, body)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use axum::http::{header::CONTENT_ENCODING, HeaderMap};
+    use axum::{extract::State, routing::post, Json, Router};
+    use flate2::read::GzDecoder;
+    use opentelemetry::trace::{Tracer, TracerProvider as _};
+    use opentelemetry_sdk::export::trace::SpanExporter as _;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use serde_json::Value;
+
+    use super::*;
+
+    /// Starts a local HTTP stand-in for a Zipkin collector, returning its endpoint and the
+    /// captured spans.
+    async fn zipkin_stand_in() -> (String, Arc<Mutex<Vec<Value>>>) {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route(
+                "/api/v2/spans",
+                post(
+                    |State(captured): State<Arc<Mutex<Vec<Value>>>>,
+                     Json(spans): Json<Vec<Value>>| async move {
+                        captured.lock().unwrap().extend(spans);
+                    },
+                ),
+            )
+            .with_state(captured.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (format!("http://{address}/api/v2/spans"), captured)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_zipkin_exporter_posts_spans() {
+        let (endpoint, captured) = zipkin_stand_in().await;
+        let processor = SpanExporterConfig::Zipkin(ZipkinExporterConfig::new(endpoint))
+            .build_span_processor("auth-webhook")
+            .unwrap();
+        let provider = TracerProvider::builder()
+            .with_span_processor(processor)
+            .build();
+
+        provider.tracer("test").in_span("request", |_| {});
+        provider.force_flush();
+
+        let spans = captured.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0]["name"], "request");
+        assert_eq!(spans[0]["localEndpoint"]["serviceName"], "auth-webhook");
+    }
+
+    /// The headers and body of the last request received by a stand-in collector.
+    type CapturedRequest = Arc<Mutex<Option<(HeaderMap, Bytes)>>>;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_http_export_compresses_with_gzip() {
+        let captured = Arc::new(Mutex::new(None));
+        let router =
+            Router::new()
+                .route(
+                    OTLP_HTTP_TRACES_PATH,
+                    post(
+                        |State(captured): State<CapturedRequest>,
+                         headers: HeaderMap,
+                         body: Bytes| async move {
+                            *captured.lock().unwrap() = Some((headers, body));
+                        },
+                    ),
+                )
+                .with_state(captured.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let config = OtlpExporterConfig::new(format!("http://{address}"))
+            .with_protocol(OtlpProtocol::HttpJson)
+            .with_compression(Some(Compression::Gzip));
+        let provider = TracerProvider::builder()
+            .with_span_processor(
+                SpanExporterConfig::Otlp(config)
+                    .build_span_processor("auth-webhook")
+                    .unwrap(),
+            )
+            .build();
+
+        provider.tracer("test").in_span("request", |_| {});
+        provider.force_flush();
+
+        let (headers, body) = captured.lock().unwrap().take().expect("no export received");
+        assert_eq!(headers[CONTENT_ENCODING], "gzip");
+        let mut json = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut json).unwrap();
+        let json: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            json["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["name"],
+            "request"
+        );
+    }
+
+    #[test]
+    fn test_parse_otlp_headers() {
+        let headers = parse_otlp_headers("x-api-key=secret, tenant = a=b,invalid");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers["tenant"], "a=b");
+
+        let headers =
+            parse_otlp_headers("Authorization=Basic%20dXNlcg%3D%3D,x%2Dkey=%E2%9C%93,bad=%FF");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["Authorization"], "Basic dXNlcg==");
+        assert_eq!(headers["x-key"], "\u{2713}");
+    }
+
+    #[tokio::test]
+    async fn test_http_export_times_out() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // A collector accepting the connections and never replying.
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let mut exporter = OtlpExporterConfig::new(format!("http://{address}"))
+            .with_protocol(OtlpProtocol::HttpProtobuf)
+            .with_timeout(Duration::from_millis(100))
+            .build_span_exporter()
+            .unwrap();
+
+        let result =
+            tokio::time::timeout(Duration::from_secs(5), exporter.export(Vec::new())).await;
+        assert!(matches!(result, Ok(Err(_))), "{result:?}");
+    }
+
+    #[test]
+    fn test_http_signal_endpoint() {
+        assert_eq!(
+            http_signal_endpoint("http://localhost:4318", OTLP_HTTP_TRACES_PATH),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            http_signal_endpoint("http://localhost:4318/custom", OTLP_HTTP_TRACES_PATH),
+            "http://localhost:4318/custom"
+        );
+        assert_eq!(
+            http_signal_endpoint("http://localhost:4318/v1/traces", OTLP_HTTP_METRICS_PATH),
+            "http://localhost:4318/v1/metrics"
+        );
+    }
+}
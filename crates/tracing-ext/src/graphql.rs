@@ -1,30 +1,242 @@
+use std::net::SocketAddr;
+
+use axum::body::HttpBody;
+use axum::extract::{ConnectInfo, MatchedPath};
 use axum_core::body::Body;
+use http::header;
 use opentelemetry::global::get_text_map_propagator;
-use opentelemetry_http::HeaderInjector;
+use opentelemetry::trace::{get_active_span, FutureExt, SpanKind};
+use opentelemetry::KeyValue;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_semantic_conventions as semcov;
 
 use crate::{global_tracer, SpanVisibility, TraceableHttpResponse};
 
+/// Traces every request in a server span continuing the trace of the caller.
+///
+/// The span is named after the method and the matched route, e.g. `POST /validate-request`, and
+/// records the HTTP server semantic-convention attributes. The client address is read from
+/// `X-Forwarded-For`, or from [`ConnectInfo`] when the router is served with
+/// `into_make_service_with_connect_info::<SocketAddr>()`.
 pub async fn graphql_request_tracing_middleware(
     request: http::Request<Body>,
     next: axum::middleware::Next,
 ) -> axum::response::Result<axum::response::Response> {
+    let parent_context = get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let name = match &route {
+        Some(route) => format!("{} {}", request.method(), route),
+        None => request.method().to_string(),
+    };
+    // Without a route, fall back to the actual path for the error descriptions.
+    let path = route.unwrap_or_else(|| request.uri().path().to_string());
+    let attributes = request_attributes(&request);
+
     let traceable = global_tracer()
-        .in_span_async_with_parent_context(
-            "request",
-            "request",
+        .in_span_async_with_kind(
+            name.clone(),
+            name,
             SpanVisibility::User,
-            &request.headers().clone(),
+            SpanKind::Server,
+            attributes,
             || {
                 Box::pin(async move {
                     let mut response = next.run(request).await;
 
+                    get_active_span(|span| {
+                        for attribute in response_attributes(&response) {
+                            span.set_attribute(attribute);
+                        }
+                    });
                     get_text_map_propagator(|propagator| {
                         propagator.inject(&mut HeaderInjector(response.headers_mut()))
                     });
-                    TraceableHttpResponse::new(response, "")
+                    TraceableHttpResponse::new(response, path)
                 })
             },
         )
+        .with_context(parent_context)
         .await;
     Ok(traceable.response)
 }
+
+fn request_attributes(request: &http::Request<Body>) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::new(
+            semcov::trace::HTTP_REQUEST_METHOD,
+            request.method().to_string(),
+        ),
+        KeyValue::new(
+            semcov::trace::URL_SCHEME,
+            request.uri().scheme_str().unwrap_or("http").to_string(),
+        ),
+        KeyValue::new(semcov::trace::URL_PATH, request.uri().path().to_string()),
+    ];
+    if let Some(route) = request.extensions().get::<MatchedPath>() {
+        attributes.push(KeyValue::new(
+            semcov::trace::HTTP_ROUTE,
+            route.as_str().to_string(),
+        ));
+    }
+    if let Some(version) = protocol_version(request.version()) {
+        attributes.push(KeyValue::new(
+            semcov::trace::NETWORK_PROTOCOL_VERSION,
+            version,
+        ));
+    }
+    if let Some(user_agent) = header_value(request.headers(), header::USER_AGENT) {
+        attributes.push(KeyValue::new(
+            semcov::trace::USER_AGENT_ORIGINAL,
+            user_agent.to_string(),
+        ));
+    }
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+    if let Some(peer) = peer {
+        attributes.push(KeyValue::new(
+            semcov::trace::NETWORK_PEER_ADDRESS,
+            peer.ip().to_string(),
+        ));
+        attributes.push(KeyValue::new(
+            semcov::attribute::NETWORK_PEER_PORT,
+            i64::from(peer.port()),
+        ));
+    }
+    // The first address forwarded by the proxies is the original client.
+    let forwarded_for = header_value(request.headers(), "x-forwarded-for")
+        .and_then(|forwarded_for| forwarded_for.split(',').next())
+        .map(|client| client.trim().to_string())
+        .filter(|client| !client.is_empty());
+    if let Some(client) = forwarded_for.or_else(|| peer.map(|peer| peer.ip().to_string())) {
+        attributes.push(KeyValue::new(semcov::trace::CLIENT_ADDRESS, client));
+    }
+    if let Some(size) = body_size(request.headers(), request.body()) {
+        attributes.push(KeyValue::new(
+            semcov::attribute::HTTP_REQUEST_BODY_SIZE,
+            size,
+        ));
+    }
+    attributes
+}
+
+fn response_attributes(response: &axum::response::Response) -> Vec<KeyValue> {
+    let status = response.status();
+    let mut attributes = vec![KeyValue::new(
+        semcov::trace::HTTP_RESPONSE_STATUS_CODE,
+        i64::from(status.as_u16()),
+    )];
+    // Only server errors are errors of a server span, client errors are the caller's.
+    if status.is_server_error() {
+        attributes.push(KeyValue::new(
+            semcov::trace::ERROR_TYPE,
+            status.as_str().to_string(),
+        ));
+    }
+    if let Some(size) = body_size(response.headers(), response.body()) {
+        attributes.push(KeyValue::new(
+            semcov::attribute::HTTP_RESPONSE_BODY_SIZE,
+            size,
+        ));
+    }
+    attributes
+}
+
+fn header_value(headers: &http::HeaderMap, name: impl header::AsHeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// The body size from `Content-Length`, or from the body itself when its size is known upfront.
+fn body_size(headers: &http::HeaderMap, body: &Body) -> Option<i64> {
+    header_value(headers, header::CONTENT_LENGTH)
+        .and_then(|length| length.parse().ok())
+        .or_else(|| body.size_hint().exact())
+        .and_then(|size| i64::try_from(size).ok())
+}
+
+fn protocol_version(version: http::Version) -> Option<&'static str> {
+    match version {
+        http::Version::HTTP_09 => Some("0.9"),
+        http::Version::HTTP_10 => Some("1.0"),
+        http::Version::HTTP_11 => Some("1.1"),
+        http::Version::HTTP_2 => Some("2"),
+        http::Version::HTTP_3 => Some("3"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(attributes: &[KeyValue], key: &str) -> Option<String> {
+        attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.to_string())
+    }
+
+    #[test]
+    fn test_request_attributes() {
+        let mut request = http::Request::post("/validate-request")
+            .header(header::USER_AGENT, "hasura-graphql-engine/v2")
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .body(Body::from("{}"))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 41234))));
+
+        let attributes = request_attributes(&request);
+        assert_eq!(
+            attribute(&attributes, "http.request.method").as_deref(),
+            Some("POST")
+        );
+        assert_eq!(
+            attribute(&attributes, "url.path").as_deref(),
+            Some("/validate-request")
+        );
+        assert_eq!(
+            attribute(&attributes, "user_agent.original").as_deref(),
+            Some("hasura-graphql-engine/v2")
+        );
+        assert_eq!(
+            attribute(&attributes, "client.address").as_deref(),
+            Some("203.0.113.7")
+        );
+        assert_eq!(
+            attribute(&attributes, "network.peer.address").as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            attribute(&attributes, "http.request.body.size").as_deref(),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn test_response_attributes() {
+        let response = http::Response::builder()
+            .status(http::StatusCode::BAD_GATEWAY)
+            .body(Body::from("bad gateway"))
+            .unwrap();
+
+        let attributes = response_attributes(&response);
+        assert_eq!(
+            attribute(&attributes, "http.response.status_code").as_deref(),
+            Some("502")
+        );
+        assert_eq!(attribute(&attributes, "error.type").as_deref(), Some("502"));
+        assert_eq!(
+            attribute(&attributes, "http.response.body.size").as_deref(),
+            Some("11")
+        );
+    }
+}
@@ -26,6 +26,8 @@
 //! }
 //! ```
 
+use std::borrow::Cow;
+
 use crate::traceable::{ErrorVisibility, Traceable, TraceableError};
 
 /// Wrapper around `http::Response<T>` that is traceable in spans.
@@ -39,14 +41,17 @@ use crate::traceable::{ErrorVisibility, Traceable, TraceableError};
 pub struct TraceableHttpResponse<T> {
     /// The HTTP response.
     pub response: http::Response<T>,
-    /// Path of the request that generated this response.
-    pub path: &'static str,
+    /// Path of the request that generated this response, preferably its route template.
+    pub path: Cow<'static, str>,
 }
 
 impl<T> TraceableHttpResponse<T> {
     /// Creates a new `TraceableHttpResponse`.
-    pub fn new(response: http::Response<T>, path: &'static str) -> Self {
-        Self { response, path }
+    pub fn new(response: http::Response<T>, path: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            response,
+            path: path.into(),
+        }
     }
 }
 
@@ -70,9 +75,10 @@ impl<T> Traceable for TraceableHttpResponse<T> {
     type ErrorType<'a> = ResponseError where T: 'a;
 
     fn get_error(&self) -> Option<Self::ErrorType<'_>> {
-        // If the response status is either client or server error, return an error.
+        // Only server errors are errors of the span, client errors are the caller's. This is the
+        // same rule as the `error.type` attribute of the server spans.
         let response_status = self.response.status();
-        if response_status.is_client_error() || response_status.is_server_error() {
+        if response_status.is_server_error() {
             Some(ResponseError {
                 error: format!(
                     "HTTP request to {} failed with status {}",
@@ -84,3 +90,25 @@ impl<T> Traceable for TraceableHttpResponse<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_error(status: http::StatusCode) -> Option<String> {
+        let response = http::Response::builder().status(status).body(()).unwrap();
+        TraceableHttpResponse::new(response, "/graphql")
+            .get_error()
+            .map(|error| error.to_string())
+    }
+
+    #[test]
+    fn test_only_server_errors_are_errors() {
+        assert!(response_error(http::StatusCode::OK).is_none());
+        assert!(response_error(http::StatusCode::NOT_FOUND).is_none());
+        assert_eq!(
+            response_error(http::StatusCode::BAD_GATEWAY).as_deref(),
+            Some("HTTP request to /graphql failed with status 502 Bad Gateway")
+        );
+    }
+}
@@ -1,25 +1,67 @@
+// Allow the code generated by `traced` to refer to `::tracing_ext` within this crate.
+extern crate self as tracing_ext;
+
+mod client;
+mod exporter;
 mod graphql;
 mod http;
+mod logging;
+mod metrics;
 mod otlp;
+mod propagation;
+mod redaction;
 mod request;
+mod resource;
+mod rolling_file;
+mod sampling;
+mod secret;
+mod task;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
 mod traceable;
 mod tracer;
 
 // Avoid conflicts with `http` crate
 pub use crate::http::TraceableHttpResponse;
+pub use client::{HttpClientError, TracedHttpClient};
+pub use exporter::{
+    parse_otlp_headers, Compression as OtlpCompression, OtlpExporterConfig, OtlpProtocol,
+    SpanExporterConfig, ZipkinExporterConfig,
+};
 pub use graphql::graphql_request_tracing_middleware;
-pub use otlp::{init_tracing, shutdown_tracer, ExportTracesStdout, PropagateBaggage};
+pub use logging::{current_trace_ids, LogFormat, LogLevelHandle, TraceIdFormat};
+pub use metrics::{init_meter_provider, SpanMetricsProcessor};
+pub use otlp::{
+    init_propagator, init_tracing, shutdown_tracer, BaggageAttributesConfig,
+    BaggageSpanProcessor, ExportTracesStdout, PropagateBaggage, TracingConfig,
+};
+pub use propagation::{parse_propagators, PropagatorKind, DEFAULT_PROPAGATORS};
+pub use redaction::{RedactionAction, RedactionConfig, RedactionSpanProcessor};
 pub use request::get_trace_headers;
-pub use traceable::{ErrorVisibility, Successful, Traceable, TraceableError};
+pub use resource::{
+    ContainerResourceDetector, HostResourceDetector, KubernetesResourceDetector,
+    ProcessResourceDetector, ServiceInfo,
+};
+pub use rolling_file::{LogRotation, RollingFileConfig, RollingFileGuard, RollingFileWriter};
+pub use sampling::{TailSamplingConfig, TailSamplingSpanProcessor};
+pub use secret::{HeaderRedactor, RedactedHeaders, Secret, DEFAULT_SENSITIVE_HEADERS, REDACTED};
+pub use task::{spawn_in_current_context, spawn_linked_trace};
+pub use tracing_ext_macros::traced;
+pub use traceable::{
+    ErrorVisibility, Successful, Traceable, TraceableError, TraceableResultExt, TraceableStdError,
+};
 pub use tracer::{
-    add_event_on_active_span, global_tracer, run_with_baggage, set_attribute_on_active_span,
-    set_status_on_current_span, AttributeValue, AttributeVisibility, SpanLink, SpanVisibility,
+    add_event_on_active_span, add_span_event_on_active_span, global_tracer,
+    record_exception_on_active_span, run_with_baggage, set_attribute_on_active_span,
+    set_status_on_current_span, AttributeValue, AttributeVisibility, SpanEvent, SpanLink,
+    SpanVisibility,
 };
 
 // re-export things from OpenTelemetry to avoid library users importing their own version and
 // risking mismatches and multiple globals
 pub use opentelemetry::baggage;
 pub use opentelemetry::global::get_text_map_propagator;
+pub use opentelemetry::metrics::MeterProvider;
 pub use opentelemetry::propagation::text_map_propagator::TextMapPropagator;
 pub use opentelemetry::trace::get_active_span;
 pub use opentelemetry::trace::FutureExt;
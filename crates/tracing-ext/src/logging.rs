@@ -0,0 +1,458 @@
+//! Correlation of log lines with traces.
+//!
+//! [`TraceIdFormat`] wraps an event formatter of `tracing-subscriber` and stamps the trace and span
+//! IDs of the current OpenTelemetry context onto every log event, so that a log line leads to its
+//! trace:
+//! - text lines are prefixed with `trace_id=<id> span_id=<id>`,
+//! - JSON records get `trace_id` and `span_id` fields.
+//!
+//! Events logged outside of an OpenTelemetry span are left untouched.
+//!
+//! # Example:
+//! ```
+//! use tracing_ext::TraceIdFormat;
+//! use tracing_subscriber::{fmt, prelude::*};
+//!
+//! tracing_subscriber::registry()
+//!     .with(fmt::layer().event_format(TraceIdFormat::text(fmt::format())))
+//!     .init();
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceError, TraceId};
+use opentelemetry::Context;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::{DefaultFields, Format, JsonFields, Writer};
+use tracing_subscriber::fmt::{
+    self as subscriber_fmt, FmtContext, FormatEvent, FormatFields, MakeWriter,
+};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use crate::rolling_file::RollingFileWriter;
+
+/// Format of the log lines written by [`crate::init_tracing`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, written to stdout without and to stderr with timestamps
+    #[default]
+    Text,
+    /// Shorter human-readable lines written to stderr
+    Compact,
+    /// JSON records written to stderr, with the timestamp, level, target, fields, current span
+    /// and span stack, and the trace and span IDs
+    Json,
+}
+
+/// Parses `text`, `compact` or `json`.
+impl FromStr for LogFormat {
+    type Err = TraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unsupported log format: {s}").into()),
+        }
+    }
+}
+
+/// The `fmt` layers writing the log lines in the given `format`, with the trace and span IDs, to the
+/// standard streams and to the log `file` if any.
+pub(crate) fn log_layers<S>(
+    format: LogFormat,
+    file: Option<RollingFileWriter>,
+) -> Vec<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    log_layers_to(format, std::io::stdout, std::io::stderr, file)
+}
+
+/// [`log_layers`] writing to the given `stdout`, `stderr` and `file` sinks.
+fn log_layers_to<S, O, E, F>(
+    format: LogFormat,
+    stdout: O,
+    stderr: E,
+    file: Option<F>,
+) -> Vec<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    O: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    E: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    F: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let stderr_layer = format_layer(format, subscriber_fmt::layer().with_writer(stderr));
+    let mut layers = match format {
+        LogFormat::Text => vec![
+            subscriber_fmt::layer()
+                .with_writer(stdout)
+                .event_format(TraceIdFormat::text(subscriber_fmt::format().without_time()))
+                .boxed(),
+            stderr_layer,
+        ],
+        LogFormat::Compact | LogFormat::Json => vec![stderr_layer],
+    };
+    if let Some(file) = file {
+        layers.push(format_layer(
+            format,
+            subscriber_fmt::layer().with_writer(file).with_ansi(false),
+        ));
+    }
+    layers
+}
+
+/// The `layer` writing the log lines in the given `format`, with the trace and span IDs.
+fn format_layer<S, W>(
+    format: LogFormat,
+    layer: subscriber_fmt::Layer<S, DefaultFields, Format, W>,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => layer
+            .event_format(TraceIdFormat::text(subscriber_fmt::format()))
+            .boxed(),
+        LogFormat::Compact => layer
+            .event_format(TraceIdFormat::text(subscriber_fmt::format().compact()))
+            .boxed(),
+        LogFormat::Json => layer
+            .fmt_fields(JsonFields::new())
+            .event_format(TraceIdFormat::json(
+                subscriber_fmt::format()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true),
+            ))
+            .boxed(),
+    }
+}
+
+/// Where the trace IDs are written by [`TraceIdFormat`].
+#[derive(Debug, Clone, Copy)]
+enum Output {
+    Text,
+    Json,
+}
+
+/// Handle to change the log filter directives at runtime, e.g. to turn on `auth_webhook=debug`
+/// during an incident without a restart.
+///
+/// The directives use the `RUST_LOG` syntax. Targets without a directive are logged at `INFO`.
+#[derive(Debug, Clone)]
+pub struct LogLevelHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    initial_directives: String,
+}
+
+impl LogLevelHandle {
+    /// Creates the reloadable filter installed by [`crate::init_tracing`], from `RUST_LOG`.
+    pub(crate) fn from_default_env() -> (reload::Layer<EnvFilter, Registry>, Self) {
+        let filter = EnvFilter::from_default_env().add_directive(LevelFilter::INFO.into());
+        let initial_directives = filter.to_string();
+        let (layer, handle) = reload::Layer::new(filter);
+        (
+            layer,
+            Self {
+                handle,
+                initial_directives,
+            },
+        )
+    }
+
+    /// The current filter directives.
+    pub fn directives(&self) -> Result<String, TraceError> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .map_err(|error| format!("failed to read the log filter: {error}").into())
+    }
+
+    /// The filter directives set at startup.
+    pub fn initial_directives(&self) -> &str {
+        &self.initial_directives
+    }
+
+    /// Replaces the filter directives, e.g. `auth_webhook=debug,tower_http=warn`.
+    pub fn set_directives(&self, directives: &str) -> Result<(), TraceError> {
+        let filter = EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .parse(directives)
+            .map_err(|error| TraceError::from(format!("invalid log filter directives: {error}")))?;
+        self.handle
+            .reload(filter)
+            .map_err(|error| format!("failed to reload the log filter: {error}").into())
+    }
+
+    /// Restores the filter directives set at startup.
+    pub fn reset(&self) -> Result<(), TraceError> {
+        self.set_directives(&self.initial_directives)
+    }
+
+    /// Switches to `directives` if the startup directives are in effect, and back to the startup
+    /// directives otherwise. Returns the directives in effect afterwards.
+    pub fn toggle(&self, directives: &str) -> Result<String, TraceError> {
+        if self.directives()? == self.initial_directives {
+            self.set_directives(directives)?;
+        } else {
+            self.reset()?;
+        }
+        self.directives()
+    }
+}
+
+/// Event formatter stamping the trace and span IDs of the current context onto the events formatted
+/// by `inner`.
+#[derive(Debug, Clone)]
+pub struct TraceIdFormat<F> {
+    inner: F,
+    output: Output,
+}
+
+impl<F> TraceIdFormat<F> {
+    /// Prefixes the text lines formatted by `inner` with the trace and span IDs.
+    pub fn text(inner: F) -> Self {
+        Self {
+            inner,
+            output: Output::Text,
+        }
+    }
+
+    /// Adds `trace_id` and `span_id` fields to the JSON records formatted by `inner`.
+    pub fn json(inner: F) -> Self {
+        Self {
+            inner,
+            output: Output::Json,
+        }
+    }
+}
+
+/// The trace and span IDs of the current OpenTelemetry span, if any.
+pub fn current_trace_ids() -> Option<(TraceId, SpanId)> {
+    let context = Context::current();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| (span_context.trace_id(), span_context.span_id()))
+}
+
+impl<S, N, F> FormatEvent<S, N> for TraceIdFormat<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let Some((trace_id, span_id)) = current_trace_ids() else {
+            return self.inner.format_event(ctx, writer, event);
+        };
+        match self.output {
+            Output::Text => {
+                write!(writer, "trace_id={trace_id} span_id={span_id} ")?;
+                self.inner.format_event(ctx, writer, event)
+            }
+            Output::Json => {
+                // The record is formatted in a buffer to insert the fields after its opening brace.
+                let mut record = String::new();
+                self.inner
+                    .format_event(ctx, Writer::new(&mut record), event)?;
+                match record.strip_prefix('{') {
+                    Some(fields) => {
+                        let separator = if fields.starts_with('}') { "" } else { "," };
+                        write!(
+                            writer,
+                            "{{\"trace_id\":\"{trace_id}\",\"span_id\":\"{span_id}\"{separator}{fields}"
+                        )
+                    }
+                    None => writer.write_str(&record),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::testing::{CapturedLines, InMemorySpanExporter};
+    use crate::{SpanVisibility, Successful};
+
+    /// Logs a message in a new span, returning the trace ID of the span.
+    fn log_in_span() -> TraceId {
+        let exporter = InMemorySpanExporter::new();
+        exporter
+            .tracer()
+            .in_span("request", "request", SpanVisibility::User, || {
+                tracing::info!("validated");
+                Successful::new(current_trace_ids().unwrap().0)
+            })
+            .into_inner()
+    }
+
+    #[test]
+    fn test_text_format() {
+        let lines = CapturedLines::new();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(lines.clone())
+            .with_ansi(false)
+            .event_format(TraceIdFormat::text(
+                tracing_subscriber::fmt::format().with_ansi(false),
+            ))
+            .finish();
+
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside");
+            log_in_span()
+        });
+
+        let output = lines.output();
+        let mut lines = output.lines();
+        assert!(!lines.next().unwrap().contains("trace_id="));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with(&format!("trace_id={trace_id} span_id=")));
+    }
+
+    #[test]
+    fn test_log_level_handle() {
+        let filter = EnvFilter::new("warn");
+        let (layer, handle) = reload::Layer::new(filter);
+        let handle = LogLevelHandle {
+            handle,
+            initial_directives: "warn".to_string(),
+        };
+        let lines = CapturedLines::new();
+        let subscriber = tracing_subscriber::registry().with(layer).with(
+            subscriber_fmt::layer()
+                .with_writer(lines.clone())
+                .with_ansi(false),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("hidden");
+            assert_eq!(handle.toggle("debug").unwrap(), "debug");
+            tracing::debug!("shown");
+            assert!(handle.set_directives("auth_webhook=[").is_err());
+            assert_eq!(handle.toggle("debug").unwrap(), "warn");
+            tracing::info!("hidden again");
+        });
+
+        let output = lines.output();
+        assert!(!output.contains("hidden"));
+        assert!(output.contains("shown"));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let lines = CapturedLines::new();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(lines.clone())
+            .fmt_fields(JsonFields::new())
+            .event_format(TraceIdFormat::json(
+                tracing_subscriber::fmt::format().json(),
+            ))
+            .finish();
+
+        let trace_id = tracing::subscriber::with_default(subscriber, log_in_span);
+
+        let record: serde_json::Value = serde_json::from_str(lines.output().trim()).unwrap();
+        assert_eq!(record["trace_id"], trace_id.to_string());
+        assert_eq!(record["span_id"].as_str().unwrap().len(), 16);
+        assert_eq!(record["fields"]["message"], "validated");
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert_eq!("compact".parse::<LogFormat>().unwrap(), LogFormat::Compact);
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("JSON".parse::<LogFormat>().is_err());
+        assert!("".parse::<LogFormat>().is_err());
+    }
+
+    /// Removes the ANSI escape sequences coloring `line`.
+    fn strip_ansi(line: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                stripped.push(c);
+            }
+        }
+        stripped
+    }
+
+    #[test]
+    fn test_log_layers_sinks() {
+        for format in [LogFormat::Text, LogFormat::Compact, LogFormat::Json] {
+            let stdout = CapturedLines::new();
+            let stderr = CapturedLines::new();
+            let file = CapturedLines::new();
+            let subscriber = tracing_subscriber::registry().with(log_layers_to(
+                format,
+                stdout.clone(),
+                stderr.clone(),
+                Some(file.clone()),
+            ));
+
+            tracing::subscriber::with_default(subscriber, || tracing::info!("validated"));
+
+            // Timestamps start with the year.
+            let starts_with_time = |line: &str| line.starts_with(|c: char| c.is_ascii_digit());
+            let stdout = strip_ansi(&stdout.output());
+            let stderr = strip_ansi(&stderr.output());
+            let file = file.output();
+            if format == LogFormat::Text {
+                assert!(stdout.contains("validated"));
+                assert!(!starts_with_time(&stdout), "{stdout}");
+            } else {
+                assert!(stdout.is_empty(), "{format:?}: {stdout}");
+            }
+            assert!(stderr.contains("validated"), "{format:?}");
+            assert!(file.contains("validated"), "{format:?}");
+            assert!(!file.contains('\x1b'), "{format:?}: {file}");
+            if format != LogFormat::Json {
+                assert!(starts_with_time(&stderr), "{stderr}");
+                assert!(starts_with_time(&file), "{file}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_layer() {
+        let lines = CapturedLines::new();
+        let subscriber = tracing_subscriber::registry().with(format_layer(
+            LogFormat::Json,
+            subscriber_fmt::layer().with_writer(lines.clone()),
+        ));
+
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("validate_request", consumer = "acme").in_scope(log_in_span)
+        });
+
+        let record: serde_json::Value = serde_json::from_str(lines.output().trim()).unwrap();
+        assert!(record["timestamp"].is_string());
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["fields"]["message"], "validated");
+        assert_eq!(record["span"]["name"], "validate_request");
+        assert_eq!(record["span"]["consumer"], "acme");
+        assert_eq!(record["spans"][0]["name"], "validate_request");
+        assert_eq!(record["trace_id"], trace_id.to_string());
+    }
+}
@@ -0,0 +1,247 @@
+//! RED metrics derived from the finished spans.
+//!
+//! [`SpanMetricsProcessor`] records, for every finished span:
+//! - `span.calls`, a counter of the spans (the request rate),
+//! - `span.errors`, a counter of the spans with an error status (the error rate),
+//! - `span.duration`, a histogram of the span durations in seconds.
+//!
+//! The metrics have the `span.name`, `span.visibility` (`user` or `internal`, as set by the
+//! [`Tracer`](crate::tracer::Tracer) methods) and `status.code` (`ok`, `error` or `unset`)
+//! dimensions, so every `Tracer::in_span` call site gets RED dashboards without hand-written
+//! metrics.
+//!
+//! The processor records with any [`Meter`], e.g. from a Prometheus meter provider or from the
+//! OTLP meter provider built by [`init_meter_provider`].
+//!
+//! # Example:
+//! ```
+//! use opentelemetry::metrics::MeterProvider as _;
+//! use opentelemetry_sdk::metrics::SdkMeterProvider;
+//! use opentelemetry_sdk::trace::TracerProvider;
+//! use tracing_ext::SpanMetricsProcessor;
+//!
+//! let meter_provider = SdkMeterProvider::builder().build();
+//! let tracer_provider = TracerProvider::builder()
+//!     .with_span_processor(SpanMetricsProcessor::new(&meter_provider.meter("tracing-ext")))
+//!     .build();
+//! ```
+
+use std::time::Duration;
+
+use opentelemetry::global;
+use opentelemetry::metrics::{Counter, Histogram, Meter};
+use opentelemetry::trace::{Status, TraceResult};
+use opentelemetry::{Context, KeyValue, Value};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::metrics::{MetricError, PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::runtime::Tokio;
+use opentelemetry_sdk::trace::{Span, SpanProcessor};
+use opentelemetry_sdk::Resource;
+
+use crate::exporter::OtlpExporterConfig;
+
+/// Bucket boundaries of the duration histogram in seconds, from 5ms to 10s.
+const DURATION_BOUNDARIES: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
+];
+
+/// A span processor recording the rate, errors and duration of the finished spans.
+#[derive(Debug)]
+pub struct SpanMetricsProcessor {
+    calls: Counter<u64>,
+    errors: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+impl SpanMetricsProcessor {
+    /// Creates a new `SpanMetricsProcessor` recording with the instruments of `meter`.
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            calls: meter
+                .u64_counter("span.calls")
+                .with_description("Number of finished spans")
+                .with_unit("{span}")
+                .build(),
+            errors: meter
+                .u64_counter("span.errors")
+                .with_description("Number of finished spans with an error status")
+                .with_unit("{span}")
+                .build(),
+            duration: meter
+                .f64_histogram("span.duration")
+                .with_description("Duration of the finished spans")
+                .with_unit("s")
+                .with_boundaries(DURATION_BOUNDARIES.to_vec())
+                .build(),
+        }
+    }
+}
+
+/// The dimensions of the metrics of `span`.
+fn span_dimensions(span: &SpanData) -> [KeyValue; 3] {
+    let visibility = span
+        .attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == "internal.visibility")
+        .map(|attribute| attribute.value.clone())
+        .unwrap_or_else(|| Value::from("unknown"));
+    let status = match span.status {
+        Status::Unset => "unset",
+        Status::Ok => "ok",
+        Status::Error { .. } => "error",
+    };
+    [
+        KeyValue::new("span.name", span.name.clone()),
+        KeyValue::new("span.visibility", visibility),
+        KeyValue::new("status.code", status),
+    ]
+}
+
+impl SpanProcessor for SpanMetricsProcessor {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        let dimensions = span_dimensions(&span);
+        self.calls.add(1, &dimensions);
+        if let Status::Error { .. } = span.status {
+            self.errors.add(1, &dimensions);
+        }
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default();
+        self.duration.record(duration.as_secs_f64(), &dimensions);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        // The metrics are flushed by their meter provider.
+        Ok(())
+    }
+
+    fn shutdown(&self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+/// Builds a meter provider periodically exporting the metrics to the OTLP collector of `exporter`,
+/// and sets it as the global meter provider.
+///
+/// The returned provider should be shut down before exiting, to export the last metrics.
+pub fn init_meter_provider(
+    resource: Resource,
+    exporter: &OtlpExporterConfig,
+    interval: Duration,
+) -> Result<SdkMeterProvider, MetricError> {
+    let reader = PeriodicReader::builder(exporter.build_metric_exporter()?, Tokio)
+        .with_interval(interval)
+        .build();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_resource(resource)
+        .with_reader(reader)
+        .build();
+    global::set_meter_provider(meter_provider.clone());
+    Ok(meter_provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Weak};
+
+    use opentelemetry::global::BoxedTracer;
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::metrics::data::{Histogram as HistogramData, ResourceMetrics, Sum};
+    use opentelemetry_sdk::metrics::reader::MetricReader;
+    use opentelemetry_sdk::metrics::{
+        InstrumentKind, ManualReader, MetricResult, Pipeline, Temporality,
+    };
+
+    use super::*;
+    use crate::tracer::Tracer;
+    use crate::{SpanVisibility, Successful, TraceableStdError};
+
+    /// A manual reader shared between the meter provider and the test.
+    #[derive(Debug, Clone)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> MetricResult<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> MetricResult<()> {
+            self.0.shutdown()
+        }
+
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    #[test]
+    fn test_span_metrics() {
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        let tracer_provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_span_processor(SpanMetricsProcessor::new(&meter_provider.meter("test")))
+            .build();
+        let tracer = Tracer::new(BoxedTracer::new(Box::new(tracer_provider.tracer("test"))));
+        tracer.in_span("validate", "validate", SpanVisibility::User, || {
+            Successful::new(())
+        });
+        let _ = tracer.in_span("validate", "validate", SpanVisibility::User, || {
+            Err::<(), _>(TraceableStdError::user(std::fmt::Error))
+        });
+
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut metrics).unwrap();
+        let metrics = &metrics.scope_metrics[0].metrics;
+        let metric = |name: &str| {
+            metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .unwrap()
+                .data
+                .as_any()
+        };
+
+        let calls = metric("span.calls").downcast_ref::<Sum<u64>>().unwrap();
+        assert_eq!(calls.data_points.len(), 2);
+        assert!(calls.data_points.iter().all(|point| point.value == 1
+            && point
+                .attributes
+                .contains(&KeyValue::new("span.name", "validate"))
+            && point
+                .attributes
+                .contains(&KeyValue::new("span.visibility", "user"))));
+        let errors = metric("span.errors").downcast_ref::<Sum<u64>>().unwrap();
+        assert_eq!(errors.data_points.len(), 1);
+        assert!(errors.data_points[0]
+            .attributes
+            .contains(&KeyValue::new("status.code", "error")));
+        let duration = metric("span.duration")
+            .downcast_ref::<HistogramData<f64>>()
+            .unwrap();
+        assert_eq!(
+            duration
+                .data_points
+                .iter()
+                .map(|point| point.count)
+                .sum::<u64>(),
+            2
+        );
+    }
+}
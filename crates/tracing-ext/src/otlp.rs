@@ -1,28 +1,35 @@
 use opentelemetry::{
-    baggage::BaggageExt, global, propagation::composite::TextMapCompositePropagator,
-    propagation::TextMapPropagator, trace::Span, trace::TraceError, KeyValue,
+    baggage::BaggageExt, global, metrics::Meter, trace::Span, trace::TraceError, KeyValue,
 };
-pub use opentelemetry_contrib::trace::propagator::trace_context_response::TraceContextResponsePropagator;
-use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
-use opentelemetry_sdk::trace::{SpanProcessor, TracerProvider};
-use opentelemetry_semantic_conventions as semcov;
+use opentelemetry_sdk::trace::{Builder, SpanProcessor, TracerProvider};
 use tracing_subscriber::{
-    filter::LevelFilter,
-    fmt,
     layer::SubscriberExt,    // for `with`
     util::SubscriberInitExt, // for `init`
-    EnvFilter,
 };
 
-/**
+use crate::exporter::SpanExporterConfig;
+use crate::logging::{log_layers, LogFormat, LogLevelHandle};
+use crate::metrics::SpanMetricsProcessor;
+use crate::propagation::{build_propagator, PropagatorKind, DEFAULT_PROPAGATORS};
+use crate::redaction::{RedactionConfig, RedactionSpanProcessor};
+use crate::resource::ServiceInfo;
+use crate::rolling_file::{RollingFileConfig, RollingFileWriter};
+use crate::sampling::{TailSamplingConfig, TailSamplingSpanProcessor};
+
+/*
  * This module provides functionality for OpenTelemetry tracing setup and configuration.
  * It includes support for:
- * - OTLP exporter configuration
- * - Baggage propagation
+ * - OTLP exporter configuration (gRPC, HTTP/protobuf, HTTP/JSON, headers, TLS, compression)
+ * - Zipkin exporter configuration
+ * - Baggage propagation, and baggage span attributes with allowlists and limits
  * - Stdout trace export
- * - Multiple propagators (TraceContext, Zipkin, Baggage, TraceContextResponse)
- * - Resource attributes for service identification
+ * - Text, compact or JSON log lines, with the trace and span IDs
+ * - Tail-based sampling of exported traces
+ * - Redaction of internal attributes and secrets before export
+ * - RED metrics derived from the finished spans
+ * - Configurable propagators (TraceContext, Zipkin B3, Jaeger, Baggage, TraceContextResponse)
+ * - Resource attributes for service identification, with host, process, container and
+ *   Kubernetes detection
  *
  * The implementation is adapted from the opentelemetry-rust-contrib project.
  * See https://docs.rs/opentelemetry-otlp/latest/opentelemetry_otlp/ for more details.
@@ -54,25 +61,127 @@ pub enum ExportTracesStdout {
     Disable,
 }
 
+/// Limits on the baggage entries copied onto spans by the [`BaggageSpanProcessor`]
+///
+/// When caller baggage is propagated, any caller can set baggage entries, so only the allowed
+/// keys become span attributes. Without allowed keys nor prefixes, no entry is copied, and
+/// [`init_tracing`] warns if caller baggage is propagated; [`BaggageAttributesConfig::allow_all`]
+/// copies all the entries.
+#[derive(Debug, Clone)]
+pub struct BaggageAttributesConfig {
+    /// Keys of the baggage entries to copy
+    pub allowed_keys: Vec<String>,
+    /// Prefixes of the keys of the baggage entries to copy
+    pub allowed_key_prefixes: Vec<String>,
+    /// Maximum number of entries copied onto a span
+    pub max_entries: usize,
+    /// Maximum length in bytes of the copied values, longer values are truncated
+    pub max_value_length: usize,
+    /// Prefix added to the keys of the copied attributes, e.g. `baggage.`
+    pub key_prefix: Option<String>,
+}
+
+impl Default for BaggageAttributesConfig {
+    fn default() -> Self {
+        Self {
+            allowed_keys: Vec::new(),
+            allowed_key_prefixes: Vec::new(),
+            // The maximum number of list-members in the W3C Baggage specification
+            max_entries: 64,
+            max_value_length: 256,
+            key_prefix: None,
+        }
+    }
+}
+
+impl BaggageAttributesConfig {
+    /// A configuration copying all the baggage entries, within the default limits.
+    ///
+    /// Only suitable when the callers are trusted, as any caller can then add span attributes.
+    pub fn allow_all() -> Self {
+        Self {
+            allowed_key_prefixes: vec![String::new()],
+            ..Default::default()
+        }
+    }
+
+    /// Whether no baggage entry is copied.
+    fn allows_nothing(&self) -> bool {
+        self.allowed_keys.is_empty() && self.allowed_key_prefixes.is_empty()
+    }
+
+    fn is_allowed(&self, key: &str) -> bool {
+        self.allowed_keys.iter().any(|allowed| allowed == key)
+            || self
+                .allowed_key_prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix.as_str()))
+    }
+
+    /// The attributes copied from the baggage of `cx`, sorted by key so that the same entries are
+    /// kept when there are too many.
+    fn attributes(&self, cx: &opentelemetry::Context) -> Vec<KeyValue> {
+        let mut entries: Vec<_> = cx
+            .baggage()
+            .iter()
+            .filter(|(key, _)| self.is_allowed(key.as_str()))
+            .collect();
+        entries.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        entries
+            .into_iter()
+            .take(self.max_entries)
+            .map(|(key, (value, _))| {
+                let key = match &self.key_prefix {
+                    Some(prefix) => format!("{prefix}{key}"),
+                    None => key.to_string(),
+                };
+                KeyValue::new(
+                    key,
+                    truncate(value.as_str().into_owned(), self.max_value_length),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Truncates `value` to at most `max_length` bytes, on a character boundary.
+fn truncate(mut value: String, max_length: usize) -> String {
+    if value.len() > max_length {
+        let mut length = max_length;
+        while !value.is_char_boundary(length) {
+            length -= 1;
+        }
+        value.truncate(length);
+    }
+    value
+}
+
 /// A span processor that adds baggage key-value pairs as span attributes
 ///
-/// The BaggageSpanProcessor extracts baggage from the Context and adds each
-/// key-value pair as an attribute on new spans. This allows baggage data to
-/// be visible in traces.
+/// The BaggageSpanProcessor extracts baggage from the Context and adds the
+/// key-value pairs allowed by its [`BaggageAttributesConfig`] as attributes on
+/// new spans. This allows baggage data to be visible in traces.
 ///
 /// Baggage is OpenTelemetry's mechanism for propagating key-value pairs across
 /// service boundaries. The data is passed in request headers using the W3C
 /// Baggage format (https://w3c.github.io/baggage/).
 ///
 /// Baggage can be added to the current context using Context::current_with_baggage().
-#[derive(Debug)]
-pub struct BaggageSpanProcessor();
+#[derive(Debug, Default)]
+pub struct BaggageSpanProcessor {
+    config: BaggageAttributesConfig,
+}
+
+impl BaggageSpanProcessor {
+    /// Creates a new `BaggageSpanProcessor` copying the baggage entries allowed by `config`
+    pub fn new(config: BaggageAttributesConfig) -> Self {
+        Self { config }
+    }
+}
 
 impl SpanProcessor for BaggageSpanProcessor {
     fn on_start(&self, span: &mut opentelemetry_sdk::trace::Span, cx: &opentelemetry::Context) {
-        for (key, (value, _)) in cx.baggage() {
-            span.set_attribute(KeyValue::new(key.to_string(), value.to_string()));
-        }
+        span.set_attributes(self.config.attributes(cx));
     }
 
     fn on_end(&self, _span: opentelemetry_sdk::export::trace::SpanData) {}
@@ -86,148 +195,209 @@ impl SpanProcessor for BaggageSpanProcessor {
     }
 }
 
-/// A propagator wrapper that only allows context injection, not extraction
-///
-/// This propagator wraps another TextMapPropagator but only implements the
-/// inject_context() functionality. The extract_with_context() method is a no-op
-/// that just returns the original context.
-///
-/// This is useful when you want to propagate context to downstream services
-/// but not accept context from upstream services.
+/// Configuration of [`init_tracing`]
 #[derive(Debug)]
-pub struct InjectOnlyTextMapPropagator<T>(T);
+pub struct TracingConfig {
+    /// Name, version and environment of the service for resource attribution
+    pub service: ServiceInfo,
+    /// OTLP or Zipkin exporter, spans are not exported when `None`
+    pub exporter: Option<SpanExporterConfig>,
+    /// Propagators used to extract and inject the context
+    pub propagators: Vec<PropagatorKind>,
+    /// Whether to propagate baggage from upstream
+    pub propagate_caller_baggage: PropagateBaggage,
+    /// Baggage entries copied onto the spans
+    pub baggage_attributes: BaggageAttributesConfig,
+    /// Whether to export traces to stdout
+    pub export_traces_stdout: ExportTracesStdout,
+    /// Tail-based sampling applied to the exporter
+    pub tail_sampling: Option<TailSamplingConfig>,
+    /// Redaction applied to the exported spans
+    pub redaction: Option<RedactionConfig>,
+    /// Format of the log lines
+    pub log_format: LogFormat,
+    /// Log file written alongside the standard streams
+    pub log_file: Option<RollingFileConfig>,
+    /// Meter recording the RED metrics of the finished spans
+    pub span_metrics: Option<Meter>,
+}
+
+impl TracingConfig {
+    /// Creates a new `TracingConfig` for the given `service`, without exporter and with the
+    /// default propagators.
+    pub fn new(service: ServiceInfo) -> Self {
+        Self {
+            service,
+            exporter: None,
+            propagators: DEFAULT_PROPAGATORS.to_vec(),
+            propagate_caller_baggage: PropagateBaggage::Disable,
+            baggage_attributes: BaggageAttributesConfig::default(),
+            export_traces_stdout: ExportTracesStdout::Disable,
+            tail_sampling: None,
+            redaction: None,
+            log_format: LogFormat::default(),
+            log_file: None,
+            span_metrics: None,
+        }
+    }
+
+    /// Sets the span exporter.
+    pub fn with_exporter(mut self, exporter: Option<SpanExporterConfig>) -> Self {
+        self.exporter = exporter;
+        self
+    }
+
+    /// Sets the propagators used to extract and inject the context.
+    pub fn with_propagators(mut self, propagators: Vec<PropagatorKind>) -> Self {
+        self.propagators = propagators;
+        self
+    }
+
+    /// Sets whether to propagate baggage from upstream.
+    pub fn with_propagate_caller_baggage(mut self, propagate: PropagateBaggage) -> Self {
+        self.propagate_caller_baggage = propagate;
+        self
+    }
+
+    /// Sets the baggage entries copied onto the spans.
+    pub fn with_baggage_attributes(mut self, baggage_attributes: BaggageAttributesConfig) -> Self {
+        self.baggage_attributes = baggage_attributes;
+        self
+    }
+
+    /// Sets whether to export traces to stdout.
+    pub fn with_export_traces_stdout(mut self, export: ExportTracesStdout) -> Self {
+        self.export_traces_stdout = export;
+        self
+    }
+
+    /// Sets the tail-based sampling applied to the exporter.
+    pub fn with_tail_sampling(mut self, tail_sampling: Option<TailSamplingConfig>) -> Self {
+        self.tail_sampling = tail_sampling;
+        self
+    }
 
-impl<T: TextMapPropagator> TextMapPropagator for InjectOnlyTextMapPropagator<T> {
-    fn inject_context(
-        &self,
-        cx: &opentelemetry::Context,
-        injector: &mut dyn opentelemetry::propagation::Injector,
-    ) {
-        self.0.inject_context(cx, injector);
+    /// Sets the redaction applied to the exported spans.
+    pub fn with_redaction(mut self, redaction: Option<RedactionConfig>) -> Self {
+        self.redaction = redaction;
+        self
     }
 
-    fn extract_with_context(
-        &self,
-        cx: &opentelemetry::Context,
-        _extractor: &dyn opentelemetry::propagation::Extractor,
-    ) -> opentelemetry::Context {
-        cx.clone()
+    /// Sets the format of the log lines.
+    pub fn with_log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
     }
 
-    fn fields(&self) -> opentelemetry::propagation::text_map_propagator::FieldIter<'_> {
-        self.0.fields()
+    /// Sets the log file written alongside the standard streams.
+    pub fn with_log_file(mut self, log_file: Option<RollingFileConfig>) -> Self {
+        self.log_file = log_file;
+        self
+    }
+
+    /// Sets the meter recording the RED metrics of the finished spans, see
+    /// [`SpanMetricsProcessor`].
+    pub fn with_span_metrics(mut self, meter: Option<Meter>) -> Self {
+        self.span_metrics = meter;
+        self
     }
 }
 
 /// Initialize OpenTelemetry tracing with the specified configuration
 ///
 /// This sets up:
+/// - Logging in the configured format, filtered by `RUST_LOG` and reloadable at runtime
+/// - Rolling log file (if configured)
 /// - Global tracer provider
-/// - OTLP exporter (if endpoint provided)
+/// - OTLP or Zipkin exporter (if configured)
 /// - Stdout exporter (if enabled)
-/// - Context propagation via standard headers
+/// - Context propagation via the configured propagators, even without an exporter
 /// - Baggage propagation (configurable)
+/// - Baggage entries copied onto the spans (configurable)
+/// - Tail-based sampling (if configured)
+/// - Redaction of the exported spans (if configured)
+/// - RED metrics of the finished spans (if configured)
 /// - Resource attributes for service identification
 ///
-/// # Arguments
-///
-/// * `service_name` - Name of the service for resource attribution
-/// * `service_version` - Version of the service for resource attribution  
-/// * `endpoint` - Optional OTLP endpoint URL (e.g. "http://localhost:4317")
-/// * `propagate_caller_baggage` - Whether to propagate baggage from upstream
-/// * `enable_stdout_export` - Whether to export traces to stdout
-///
 /// # Returns
 ///
-/// Returns `Ok(())` if setup succeeds, or a `TraceError` if initialization fails
-pub fn init_tracing(
-    service_name: &'static str,
-    service_version: &'static str,
-    endpoint: Option<&str>,
-    propagate_caller_baggage: PropagateBaggage,
-    enable_stdout_export: ExportTracesStdout,
-) -> Result<(), TraceError> {
-    // Install global collector configured based on RUST_LOG env var.
-    let env_filter = EnvFilter::from_default_env().add_directive(LevelFilter::INFO.into());
+/// Returns the handle to change the log filter at runtime if setup succeeds, or a `TraceError` if
+/// initialization fails
+pub fn init_tracing(config: TracingConfig) -> Result<LogLevelHandle, TraceError> {
+    // Install global collector configured based on RUST_LOG env var, reloadable at runtime.
+    let (env_filter, log_level) = LogLevelHandle::from_default_env();
+    let log_file = config
+        .log_file
+        .clone()
+        .map(RollingFileWriter::new)
+        .transpose()?;
 
-    // Create a `tracing` layer to emit spans as structured logs to stdout
-    let std_layer = fmt::layer().with_writer(std::io::stderr);
+    // Create the `tracing` layers to emit spans as logs in the configured format, with the IDs of
+    // the current trace to correlate the logs with the traces
     let subscriber = tracing_subscriber::registry()
         .with(env_filter)
-        .with(fmt::layer().without_time())
-        .with(std_layer);
+        .with(log_layers(config.log_format, log_file));
     subscriber.init();
 
+    // Install the propagators, independently of whether spans are exported
+    init_propagator(&config.propagators, config.propagate_caller_baggage);
+    if matches!(config.propagate_caller_baggage, PropagateBaggage::Enable)
+        && config.baggage_attributes.allows_nothing()
+    {
+        tracing::warn!(
+            "caller baggage is propagated but no baggage entry is allowed onto the spans, \
+             configure the allowed keys or prefixes to record them"
+        );
+    }
+
     // Initialize the tracer provider
-    if let Some(endpoint) = endpoint {
-        init_tracer_provider(
-            service_name,
-            service_version,
-            endpoint,
-            propagate_caller_baggage,
-            enable_stdout_export,
-        )?;
+    if let Some(exporter) = &config.exporter {
+        init_tracer_provider(&config, exporter)?;
     }
-    Ok(())
+    Ok(log_level)
 }
 
 /// Initialize the OpenTelemetry tracer provider with the specified configuration
 ///
 /// This sets up the tracer provider with:
-/// - OTLP exporter
+/// - OTLP or Zipkin exporter
 /// - Baggage processor
 /// - Resource attributes
 /// - Optional stdout exporter
+/// - Optional tail-based sampling of the exporter
+/// - Optional redaction of the exported spans
 ///
 /// # Arguments
 ///
-/// * `service_name` - Name of the service for resource attribution
-/// * `service_version` - Version of the service for resource attribution
-/// * `endpoint` - OTLP endpoint URL (e.g. "http://localhost:4317")
-/// * `propagate_caller_baggage` - Whether to propagate baggage from upstream
-/// * `enable_stdout_export` - Whether to export traces to stdout
+/// * `config` - Service, baggage, stdout export, tail sampling and redaction configuration
+/// * `exporter` - OTLP or Zipkin exporter configuration
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if setup succeeds, or a `TraceError` if initialization fails
 pub fn init_tracer_provider(
-    service_name: &'static str,
-    service_version: &'static str,
-    endpoint: &str,
-    propagate_caller_baggage: PropagateBaggage,
-    enable_stdout_export: ExportTracesStdout,
+    config: &TracingConfig,
+    exporter: &SpanExporterConfig,
 ) -> Result<(), TraceError> {
-    let baggage_propagator: Box<dyn TextMapPropagator + Send + Sync> =
-        match propagate_caller_baggage {
-            PropagateBaggage::Enable => Box::new(BaggagePropagator::new()),
-            PropagateBaggage::Disable => {
-                Box::new(InjectOnlyTextMapPropagator(BaggagePropagator::new()))
-            }
-        };
-    global::set_text_map_propagator(TextMapCompositePropagator::new(vec![
-        Box::new(TraceContextPropagator::new()),
-        Box::new(opentelemetry_zipkin::Propagator::new()),
-        baggage_propagator,
-        Box::new(TraceContextResponsePropagator::new()),
-    ]));
-
-    let resource_entries = vec![
-        KeyValue::new(semcov::resource::SERVICE_NAME, service_name),
-        KeyValue::new(semcov::resource::SERVICE_VERSION, service_version),
-    ];
-
-    let otlp_exporter_builder = opentelemetry_otlp::SpanExporter::builder()
-        .with_tonic()
-        .with_endpoint(endpoint);
-
-    let otlp_exporter = otlp_exporter_builder.build()?;
+    let export_processor = exporter.build_span_processor(config.service.name)?;
 
     let mut tracer_provider = TracerProvider::builder()
-        .with_resource(opentelemetry_sdk::Resource::new(resource_entries))
-        .with_batch_exporter(otlp_exporter, opentelemetry_sdk::runtime::Tokio)
-        .with_span_processor(BaggageSpanProcessor());
+        .with_resource(config.service.resource())
+        .with_span_processor(BaggageSpanProcessor::new(config.baggage_attributes.clone()));
+    if let Some(meter) = &config.span_metrics {
+        tracer_provider = tracer_provider.with_span_processor(SpanMetricsProcessor::new(meter));
+    }
+    let redaction = config.redaction.clone();
+    tracer_provider = match config.tail_sampling {
+        Some(tail_sampling) => with_redaction(
+            tracer_provider,
+            TailSamplingSpanProcessor::new(export_processor, tail_sampling),
+            redaction,
+        ),
+        None => with_redaction(tracer_provider, export_processor, redaction),
+    };
 
-    if let ExportTracesStdout::Enable = enable_stdout_export {
+    if let ExportTracesStdout::Enable = config.export_traces_stdout {
         let stdout_exporter = opentelemetry_stdout::SpanExporter::default();
         tracer_provider = tracer_provider.with_simple_exporter(stdout_exporter);
     }
@@ -238,9 +408,130 @@ pub fn init_tracer_provider(
     Ok(())
 }
 
+/// Adds the `processor` to the tracer provider, wrapped in a redaction processor if `redaction` is
+/// configured.
+fn with_redaction<P: SpanProcessor + 'static>(
+    tracer_provider: Builder,
+    processor: P,
+    redaction: Option<RedactionConfig>,
+) -> Builder {
+    match redaction {
+        Some(config) => {
+            tracer_provider.with_span_processor(RedactionSpanProcessor::new(processor, config))
+        }
+        None => tracer_provider.with_span_processor(processor),
+    }
+}
+
+/// Install the global text map propagator composed of the given `propagators`
+///
+/// # Arguments
+///
+/// * `propagators` - Propagators used to extract and inject the context
+/// * `propagate_caller_baggage` - Whether to propagate baggage from upstream
+pub fn init_propagator(propagators: &[PropagatorKind], propagate_caller_baggage: PropagateBaggage) {
+    global::set_text_map_propagator(build_propagator(propagators, propagate_caller_baggage));
+}
+
 /// Shutdown the global tracer provider
 ///
 /// This ensures any pending spans are exported before the program exits.
 pub fn shutdown_tracer() {
     global::shutdown_tracer_provider();
 }
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::global::BoxedTracer;
+    use opentelemetry::trace::TracerProvider as _;
+
+    use crate::testing::InMemorySpanExporter;
+    use crate::tracer::Tracer;
+    use crate::{run_with_baggage, SpanVisibility, Successful};
+
+    use super::*;
+
+    #[test]
+    fn test_baggage_span_processor() {
+        let exporter = InMemorySpanExporter::new();
+        let config = BaggageAttributesConfig {
+            allowed_keys: vec!["tenant.id".to_string()],
+            ..Default::default()
+        };
+        let provider = exporter.tracer_provider_with_baggage_attributes(config);
+        let tracer = Tracer::new(BoxedTracer::new(Box::new(provider.tracer("test"))));
+
+        run_with_baggage(
+            vec![
+                KeyValue::new("tenant.id", "acme"),
+                KeyValue::new("injected", "value"),
+            ],
+            || {
+                tracer.in_span("request", "request", SpanVisibility::User, || {
+                    Successful::new(())
+                })
+            },
+        );
+        exporter
+            .assert_span("request")
+            .has_attribute("tenant.id", "acme")
+            .has_no_attribute("injected");
+    }
+
+    #[test]
+    fn test_baggage_attributes_allowlist() {
+        let cx = opentelemetry::Context::new().with_baggage(vec![
+            KeyValue::new("tenant.id", "acme"),
+            KeyValue::new("injected", "value"),
+        ]);
+
+        // No entry is copied without an allowlist.
+        assert!(BaggageAttributesConfig::default()
+            .attributes(&cx)
+            .is_empty());
+        assert_eq!(
+            BaggageAttributesConfig::allow_all().attributes(&cx).len(),
+            2
+        );
+
+        let config = BaggageAttributesConfig {
+            allowed_key_prefixes: vec![String::new()],
+            ..Default::default()
+        };
+        assert_eq!(config.attributes(&cx).len(), 2);
+    }
+
+    #[test]
+    fn test_baggage_attributes_limits() {
+        let config = BaggageAttributesConfig {
+            allowed_keys: vec!["tenant.id".to_string()],
+            allowed_key_prefixes: vec!["app.".to_string()],
+            max_entries: 2,
+            max_value_length: 4,
+            key_prefix: Some("baggage.".to_string()),
+        };
+        let cx = opentelemetry::Context::new().with_baggage(vec![
+            KeyValue::new("tenant.id", "acme-corp"),
+            KeyValue::new("app.a", "1"),
+            KeyValue::new("app.b", "2"),
+            KeyValue::new("injected", "value"),
+        ]);
+
+        let attributes = config.attributes(&cx);
+        assert_eq!(
+            attributes,
+            vec![
+                KeyValue::new("baggage.app.a", "1"),
+                KeyValue::new("baggage.app.b", "2"),
+            ]
+        );
+
+        let config = BaggageAttributesConfig {
+            max_entries: 3,
+            ..config
+        };
+        assert!(config
+            .attributes(&cx)
+            .contains(&KeyValue::new("baggage.tenant.id", "acme")));
+    }
+}
@@ -0,0 +1,287 @@
+//! Context propagation configuration.
+//!
+//! The propagators used to extract and inject the trace context and baggage are selected by
+//! [`PropagatorKind`], following the values of the standard `OTEL_PROPAGATORS` environment
+//! variable.
+
+use std::str::FromStr;
+
+use opentelemetry::propagation::{composite::TextMapCompositePropagator, TextMapPropagator};
+use opentelemetry::trace::TraceError;
+use opentelemetry_contrib::trace::propagator::trace_context_response::TraceContextResponsePropagator;
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+use opentelemetry_zipkin::B3Encoding;
+
+use crate::otlp::PropagateBaggage;
+
+/// A context propagator that can be installed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PropagatorKind {
+    /// W3C Trace Context (`traceparent` and `tracestate` headers)
+    TraceContext,
+    /// Zipkin B3 with a single `b3` header
+    B3,
+    /// Zipkin B3 with multiple `X-B3-*` headers
+    B3Multi,
+    /// Jaeger `uber-trace-id` header
+    Jaeger,
+    /// W3C Baggage (`baggage` header)
+    Baggage,
+    /// W3C Trace Context response (`traceresponse` header)
+    TraceResponse,
+    /// No propagation
+    None,
+}
+
+/// The propagators installed when none are configured.
+pub const DEFAULT_PROPAGATORS: &[PropagatorKind] = &[
+    PropagatorKind::TraceContext,
+    PropagatorKind::B3Multi,
+    PropagatorKind::Baggage,
+    PropagatorKind::TraceResponse,
+];
+
+/// Parses the values of `OTEL_PROPAGATORS`, plus `traceresponse`.
+impl FromStr for PropagatorKind {
+    type Err = TraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tracecontext" => Ok(PropagatorKind::TraceContext),
+            "b3" => Ok(PropagatorKind::B3),
+            "b3multi" => Ok(PropagatorKind::B3Multi),
+            "jaeger" => Ok(PropagatorKind::Jaeger),
+            "baggage" => Ok(PropagatorKind::Baggage),
+            "traceresponse" => Ok(PropagatorKind::TraceResponse),
+            "none" => Ok(PropagatorKind::None),
+            _ => Err(format!("unsupported propagator: {s}").into()),
+        }
+    }
+}
+
+/// Parses a comma-separated list of propagators, e.g. `tracecontext,baggage`.
+///
+/// `none` disables propagation, so it cannot be combined with other propagators.
+pub fn parse_propagators(propagators: &str) -> Result<Vec<PropagatorKind>, TraceError> {
+    let propagators = propagators
+        .split(',')
+        .map(str::trim)
+        .filter(|propagator| !propagator.is_empty())
+        .map(PropagatorKind::from_str)
+        .collect::<Result<Vec<_>, _>>()?;
+    if propagators.len() > 1 && propagators.contains(&PropagatorKind::None) {
+        return Err("the none propagator cannot be combined with other propagators".into());
+    }
+    Ok(propagators)
+}
+
+/// A propagator wrapper that only allows context injection, not extraction
+///
+/// This propagator wraps another TextMapPropagator but only implements the
+/// inject_context() functionality. The extract_with_context() method is a no-op
+/// that just returns the original context.
+///
+/// This is useful when you want to propagate context to downstream services
+/// but not accept context from upstream services.
+#[derive(Debug)]
+pub struct InjectOnlyTextMapPropagator<T>(T);
+
+impl<T: TextMapPropagator> TextMapPropagator for InjectOnlyTextMapPropagator<T> {
+    fn inject_context(
+        &self,
+        cx: &opentelemetry::Context,
+        injector: &mut dyn opentelemetry::propagation::Injector,
+    ) {
+        self.0.inject_context(cx, injector);
+    }
+
+    fn extract_with_context(
+        &self,
+        cx: &opentelemetry::Context,
+        _extractor: &dyn opentelemetry::propagation::Extractor,
+    ) -> opentelemetry::Context {
+        cx.clone()
+    }
+
+    fn fields(&self) -> opentelemetry::propagation::text_map_propagator::FieldIter<'_> {
+        self.0.fields()
+    }
+}
+
+/// The Zipkin B3 propagator with the single `b3` header encoding.
+///
+/// `opentelemetry_zipkin::Propagator` also injects the multiple `X-B3-*` headers with this
+/// encoding, so only its `b3` header is kept.
+#[derive(Debug)]
+struct B3SingleHeaderPropagator {
+    inner: opentelemetry_zipkin::Propagator,
+    fields: [String; 1],
+}
+
+impl B3SingleHeaderPropagator {
+    const HEADER: &'static str = "b3";
+
+    fn new() -> Self {
+        Self {
+            inner: opentelemetry_zipkin::Propagator::with_encoding(B3Encoding::SingleHeader),
+            fields: [Self::HEADER.to_string()],
+        }
+    }
+}
+
+/// An injector only setting the `b3` header.
+struct B3SingleHeaderInjector<'a>(&'a mut dyn opentelemetry::propagation::Injector);
+
+impl opentelemetry::propagation::Injector for B3SingleHeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if key == B3SingleHeaderPropagator::HEADER {
+            self.0.set(key, value);
+        }
+    }
+}
+
+impl TextMapPropagator for B3SingleHeaderPropagator {
+    fn inject_context(
+        &self,
+        cx: &opentelemetry::Context,
+        injector: &mut dyn opentelemetry::propagation::Injector,
+    ) {
+        self.inner
+            .inject_context(cx, &mut B3SingleHeaderInjector(injector));
+    }
+
+    fn extract_with_context(
+        &self,
+        cx: &opentelemetry::Context,
+        extractor: &dyn opentelemetry::propagation::Extractor,
+    ) -> opentelemetry::Context {
+        self.inner.extract_with_context(cx, extractor)
+    }
+
+    fn fields(&self) -> opentelemetry::propagation::text_map_propagator::FieldIter<'_> {
+        opentelemetry::propagation::text_map_propagator::FieldIter::new(&self.fields)
+    }
+}
+
+/// Builds a composite propagator from the given `propagators`.
+///
+/// The baggage propagator only extracts caller baggage if `propagate_caller_baggage` is enabled.
+/// [`PropagatorKind::None`] contributes nothing, so `none` disables propagation.
+pub fn build_propagator(
+    propagators: &[PropagatorKind],
+    propagate_caller_baggage: PropagateBaggage,
+) -> TextMapCompositePropagator {
+    let propagators = propagators
+        .iter()
+        .filter_map(|kind| -> Option<Box<dyn TextMapPropagator + Send + Sync>> {
+            match kind {
+                PropagatorKind::TraceContext => Some(Box::new(TraceContextPropagator::new())),
+                PropagatorKind::B3 => Some(Box::new(B3SingleHeaderPropagator::new())),
+                PropagatorKind::B3Multi => Some(Box::new(
+                    opentelemetry_zipkin::Propagator::with_encoding(B3Encoding::MultipleHeader),
+                )),
+                PropagatorKind::Jaeger => {
+                    Some(Box::new(opentelemetry_jaeger_propagator::Propagator::new()))
+                }
+                PropagatorKind::Baggage => match propagate_caller_baggage {
+                    PropagateBaggage::Enable => Some(Box::new(BaggagePropagator::new())),
+                    PropagateBaggage::Disable => Some(Box::new(InjectOnlyTextMapPropagator(
+                        BaggagePropagator::new(),
+                    ))),
+                },
+                PropagatorKind::TraceResponse => {
+                    Some(Box::new(TraceContextResponsePropagator::new()))
+                }
+                PropagatorKind::None => None,
+            }
+        })
+        .collect();
+    TextMapCompositePropagator::new(propagators)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::Context;
+
+    use super::*;
+
+    const TRACE_ID: TraceId =
+        TraceId::from_bytes(0x4bf92f3577b34da6a3ce929d0e0e4736_u128.to_be_bytes());
+
+    fn context() -> Context {
+        Context::new().with_remote_span_context(SpanContext::new(
+            TRACE_ID,
+            SpanId::from_bytes(0x00f067aa0ba902b7_u64.to_be_bytes()),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ))
+    }
+
+    /// Injects the test context with the propagators `names`, returning the injected headers.
+    fn inject(names: &str) -> HashMap<String, String> {
+        let propagator =
+            build_propagator(&parse_propagators(names).unwrap(), PropagateBaggage::Enable);
+        let mut headers = HashMap::new();
+        propagator.inject_context(&context(), &mut headers);
+        headers
+    }
+
+    /// Extracts the trace ID from `headers` with the propagators `names`.
+    fn extract(names: &str, headers: &HashMap<String, String>) -> TraceId {
+        let propagator =
+            build_propagator(&parse_propagators(names).unwrap(), PropagateBaggage::Enable);
+        propagator.extract(headers).span().span_context().trace_id()
+    }
+
+    #[test]
+    fn test_trace_context_propagators() {
+        for (names, header) in [
+            ("tracecontext", "traceparent"),
+            ("b3", "b3"),
+            ("b3multi", "x-b3-traceid"),
+            ("jaeger", "uber-trace-id"),
+        ] {
+            let headers = inject(names);
+            assert!(headers.contains_key(header), "{names}: {headers:?}");
+            assert_eq!(extract(names, &headers), TRACE_ID, "{names}");
+        }
+
+        // Single and multiple header B3 are distinct encodings.
+        assert!(!inject("b3").contains_key("x-b3-traceid"));
+        assert!(!inject("b3multi").contains_key("b3"));
+    }
+
+    #[test]
+    fn test_trace_response_propagator() {
+        let headers = inject("traceresponse");
+        assert_eq!(
+            headers.keys().collect::<Vec<_>>(),
+            ["traceresponse"],
+            "{headers:?}"
+        );
+        assert!(inject("tracecontext,traceresponse").contains_key("traceparent"));
+    }
+
+    #[test]
+    fn test_none_propagator() {
+        assert!(inject("none").is_empty());
+        assert!(parse_propagators("none,tracecontext").is_err());
+        assert!(parse_propagators("tracecontext,none").is_err());
+    }
+
+    #[test]
+    fn test_parse_propagators() {
+        assert_eq!(
+            parse_propagators(" tracecontext, ,baggage ").unwrap(),
+            [PropagatorKind::TraceContext, PropagatorKind::Baggage]
+        );
+        assert!(parse_propagators("tracecontext,xray").is_err());
+        assert!(parse_propagators("B3").is_err());
+    }
+}
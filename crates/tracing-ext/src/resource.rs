@@ -0,0 +1,271 @@
+//! Resource detection for service telemetry.
+//!
+//! The resource attached to every exported span identifies where it was produced. On top of the
+//! service name and version, this module detects:
+//! - the host name and architecture ([`HostResourceDetector`])
+//! - the process ID, executable and runtime ([`ProcessResourceDetector`])
+//! - the container ID from the cgroup of the process ([`ContainerResourceDetector`])
+//! - Kubernetes metadata exposed through downward-API environment variables
+//!   ([`KubernetesResourceDetector`])
+//!
+//! Attributes from `OTEL_RESOURCE_ATTRIBUTES` override the detected ones, and the service
+//! attributes from [`ServiceInfo`] override both.
+
+use std::time::Duration;
+
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::resource::{
+    EnvResourceDetector, ResourceDetector, TelemetryResourceDetector,
+};
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions as semcov;
+
+/// Timeout given to the resource detectors. All of them only read local files or variables.
+const DETECTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Kubernetes downward-API environment variables and the resource attribute they are mapped to.
+/// For each attribute the first variable set wins.
+const KUBERNETES_ENV_VARS: &[(&str, &[&str])] = &[
+    (
+        semcov::resource::K8S_POD_NAME,
+        &["K8S_POD_NAME", "POD_NAME"],
+    ),
+    (semcov::resource::K8S_POD_UID, &["K8S_POD_UID", "POD_UID"]),
+    (
+        semcov::resource::K8S_NAMESPACE_NAME,
+        &["K8S_NAMESPACE_NAME", "POD_NAMESPACE"],
+    ),
+    (
+        semcov::resource::K8S_NODE_NAME,
+        &["K8S_NODE_NAME", "NODE_NAME"],
+    ),
+    (
+        semcov::resource::K8S_CONTAINER_NAME,
+        &["K8S_CONTAINER_NAME", "CONTAINER_NAME"],
+    ),
+    (
+        semcov::resource::K8S_DEPLOYMENT_NAME,
+        &["K8S_DEPLOYMENT_NAME"],
+    ),
+];
+
+/// Identifies the service producing the telemetry.
+#[derive(Debug, Clone)]
+pub struct ServiceInfo {
+    /// Name of the service (`service.name`).
+    pub name: &'static str,
+    /// Version of the service (`service.version`).
+    pub version: &'static str,
+    /// Deployment environment of the service, e.g. "production" (`deployment.environment`).
+    pub deployment_environment: Option<String>,
+}
+
+impl ServiceInfo {
+    /// Creates a new `ServiceInfo` without a deployment environment.
+    pub fn new(name: &'static str, version: &'static str) -> Self {
+        Self {
+            name,
+            version,
+            deployment_environment: None,
+        }
+    }
+
+    /// Sets the deployment environment of the service.
+    pub fn with_deployment_environment(mut self, deployment_environment: Option<String>) -> Self {
+        self.deployment_environment = deployment_environment;
+        self
+    }
+
+    /// Builds the resource describing this service, merged with the detected resources and
+    /// `OTEL_RESOURCE_ATTRIBUTES`.
+    pub fn resource(&self) -> Resource {
+        let detected = Resource::from_detectors(
+            DETECTION_TIMEOUT,
+            vec![
+                Box::new(TelemetryResourceDetector),
+                Box::new(HostResourceDetector),
+                Box::new(ProcessResourceDetector),
+                Box::new(ContainerResourceDetector),
+                Box::new(KubernetesResourceDetector),
+                Box::new(EnvResourceDetector::new()),
+            ],
+        );
+
+        let mut service_entries = vec![
+            KeyValue::new(semcov::resource::SERVICE_NAME, self.name),
+            KeyValue::new(semcov::resource::SERVICE_VERSION, self.version),
+        ];
+        if let Some(environment) = &self.deployment_environment {
+            service_entries.push(KeyValue::new(
+                semcov::resource::DEPLOYMENT_ENVIRONMENT_NAME,
+                environment.clone(),
+            ));
+            // The attribute was renamed to `deployment.environment.name`, but many backends still
+            // only read the old name.
+            service_entries.push(KeyValue::new("deployment.environment", environment.clone()));
+        }
+        detected.merge(&Resource::new(service_entries))
+    }
+}
+
+/// Detects `host.name`, `host.arch` and `os.type`.
+#[derive(Debug)]
+pub struct HostResourceDetector;
+
+impl ResourceDetector for HostResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let host_name = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| read_trimmed("/proc/sys/kernel/hostname"))
+            .or_else(|| read_trimmed("/etc/hostname"))
+            .filter(|host_name| !host_name.is_empty());
+
+        let mut entries = vec![
+            KeyValue::new(semcov::resource::HOST_ARCH, std::env::consts::ARCH),
+            KeyValue::new(semcov::resource::OS_TYPE, std::env::consts::OS),
+        ];
+        if let Some(host_name) = host_name {
+            entries.push(KeyValue::new(semcov::resource::HOST_NAME, host_name));
+        }
+        Resource::new(entries)
+    }
+}
+
+/// Detects `process.pid`, the process executable and the process runtime.
+#[derive(Debug)]
+pub struct ProcessResourceDetector;
+
+impl ResourceDetector for ProcessResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let mut entries = vec![
+            KeyValue::new(semcov::resource::PROCESS_PID, i64::from(std::process::id())),
+            KeyValue::new(semcov::resource::PROCESS_RUNTIME_NAME, "rust"),
+        ];
+        if let Ok(executable) = std::env::current_exe() {
+            if let Some(name) = executable.file_name() {
+                entries.push(KeyValue::new(
+                    semcov::resource::PROCESS_EXECUTABLE_NAME,
+                    name.to_string_lossy().into_owned(),
+                ));
+            }
+            entries.push(KeyValue::new(
+                semcov::resource::PROCESS_EXECUTABLE_PATH,
+                executable.to_string_lossy().into_owned(),
+            ));
+        }
+        Resource::new(entries)
+    }
+}
+
+/// Detects `container.id` from the cgroup of the process.
+#[derive(Debug)]
+pub struct ContainerResourceDetector;
+
+impl ResourceDetector for ContainerResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let container_id = std::fs::read_to_string("/proc/self/cgroup")
+            .ok()
+            .and_then(|cgroup| container_id_from_cgroup(&cgroup))
+            .or_else(|| {
+                std::fs::read_to_string("/proc/self/mountinfo")
+                    .ok()
+                    .and_then(|mountinfo| container_id_from_mountinfo(&mountinfo))
+            });
+        match container_id {
+            Some(container_id) => {
+                Resource::new([KeyValue::new(semcov::resource::CONTAINER_ID, container_id)])
+            }
+            None => Resource::empty(),
+        }
+    }
+}
+
+/// Detects Kubernetes metadata from the environment variables usually populated through the
+/// downward API, e.g.:
+/// ```yaml
+/// env:
+///   - name: K8S_POD_NAME
+///     valueFrom:
+///       fieldRef:
+///         fieldPath: metadata.name
+/// ```
+#[derive(Debug)]
+pub struct KubernetesResourceDetector;
+
+impl ResourceDetector for KubernetesResourceDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        Resource::new(KUBERNETES_ENV_VARS.iter().filter_map(|(key, vars)| {
+            let value = vars
+                .iter()
+                .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))?;
+            Some(KeyValue::new(*key, value))
+        }))
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+/// Container IDs are 64 hexadecimal characters.
+fn is_container_id(candidate: &str) -> bool {
+    candidate.len() == 64 && candidate.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Extracts the container ID from the last path segment of a cgroup v1 entry, e.g.
+/// `12:memory:/docker/<id>` or `0::/kubepods/burstable/pod<uid>/cri-containerd-<id>.scope`.
+fn container_id_from_cgroup(cgroup: &str) -> Option<String> {
+    cgroup.lines().find_map(|line| {
+        let segment = line.rsplit('/').next()?;
+        let segment = segment.strip_suffix(".scope").unwrap_or(segment);
+        let candidate = segment.rsplit(['-', ':']).next()?;
+        is_container_id(candidate).then(|| candidate.to_string())
+    })
+}
+
+/// Extracts the container ID from the mounts of a cgroup v2 container, which reference the
+/// container directory, e.g. `/var/lib/docker/containers/<id>/hostname`.
+fn container_id_from_mountinfo(mountinfo: &str) -> Option<String> {
+    mountinfo.lines().find_map(|line| {
+        line.split_whitespace()
+            .flat_map(|field| field.split('/'))
+            .skip_while(|segment| *segment != "containers" && *segment != "sandboxes")
+            .nth(1)
+            .filter(|candidate| is_container_id(candidate))
+            .map(str::to_string)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "7be92808767a667f35c8505cbf40d14e931ef6db5b0210329cf193b15ba9d605";
+
+    #[test]
+    fn test_container_id_from_cgroup() {
+        let docker = format!("12:memory:/docker/{ID}\n0::/\n");
+        assert_eq!(container_id_from_cgroup(&docker).as_deref(), Some(ID));
+
+        let containerd =
+            format!("0::/kubepods.slice/kubepods-pod1.slice/cri-containerd-{ID}.scope\n");
+        assert_eq!(container_id_from_cgroup(&containerd).as_deref(), Some(ID));
+
+        assert_eq!(container_id_from_cgroup("0::/\n"), None);
+    }
+
+    #[test]
+    fn test_container_id_from_mountinfo() {
+        let mountinfo = format!(
+            "681 671 254:1 /docker/containers/{ID}/hostname /etc/hostname rw,relatime - ext4 \
+             /dev/vda1 rw\n"
+        );
+        assert_eq!(container_id_from_mountinfo(&mountinfo).as_deref(), Some(ID));
+        assert_eq!(
+            container_id_from_mountinfo("22 1 0:21 / /proc rw - proc proc rw\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,448 @@
+//! Log file rotated by size or time.
+//!
+//! [`RollingFileWriter`] writes the log lines to a file, e.g. `/var/log/auth-webhook/auth.log`.
+//! When the file grows over the size limit, or when the hour or day changes, it is renamed after the
+//! UTC time of its first line, e.g. `auth.log.20241018T140000`, and a new file is started. The
+//! oldest rotated files are deleted beyond the retention limit.
+//!
+//! # Example:
+//! ```no_run
+//! use tracing_ext::{LogRotation, RollingFileConfig, RollingFileWriter};
+//!
+//! let config = RollingFileConfig::new("/var/log/auth-webhook/auth.log")
+//!     .with_rotation(LogRotation::Size(100 * 1024 * 1024))
+//!     .with_max_files(Some(7));
+//! let writer = RollingFileWriter::new(config).unwrap();
+//! tracing_subscriber::fmt().with_writer(writer).with_ansi(false).init();
+//! ```
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use opentelemetry::trace::TraceError;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Format of the time suffix of the rotated files, sorting in chronological order.
+const ROTATED_SUFFIX_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// Time without rotation attempts after a failed rotation.
+const ROTATION_RETRY_DELAY: Duration = Duration::minutes(1);
+
+/// When the log file is rotated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogRotation {
+    /// When writing a line would make the file grow over the given number of bytes
+    Size(u64),
+    /// At the start of every UTC hour
+    Hourly,
+    /// At the start of every UTC day
+    Daily,
+    /// Never, the file grows without limit
+    Never,
+}
+
+/// Parses `hourly`, `daily`, `never` or a size in bytes with an optional `KB`, `MB` or `GB` unit,
+/// e.g. `100MB`.
+impl FromStr for LogRotation {
+    type Err = TraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => return Ok(LogRotation::Hourly),
+            "daily" => return Ok(LogRotation::Daily),
+            "never" => return Ok(LogRotation::Never),
+            _ => {}
+        }
+        let upper = s.trim().to_ascii_uppercase();
+        let (size, unit) = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)]
+            .into_iter()
+            .find_map(|(suffix, unit)| upper.strip_suffix(suffix).map(|size| (size, unit)))
+            .unwrap_or((upper.as_str(), 1));
+        match size.trim().parse::<u64>() {
+            Ok(size) if size > 0 => Ok(LogRotation::Size(size.saturating_mul(unit))),
+            _ => Err(format!("unsupported log rotation: {s}").into()),
+        }
+    }
+}
+
+impl LogRotation {
+    /// The start of the next period after `time`, for the time-based rotations.
+    fn next_rotation(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let period = match self {
+            LogRotation::Hourly => Duration::hours(1),
+            LogRotation::Daily => Duration::days(1),
+            LogRotation::Size(_) | LogRotation::Never => return None,
+        };
+        time.duration_trunc(period)
+            .ok()
+            .map(|period_start| period_start + period)
+    }
+}
+
+/// Configuration of the [`RollingFileWriter`].
+#[derive(Debug, Clone)]
+pub struct RollingFileConfig {
+    /// Path of the current log file, its directory is created if missing
+    pub path: PathBuf,
+    /// When the file is rotated
+    pub rotation: LogRotation,
+    /// Number of rotated files to keep, all are kept when `None`
+    pub max_files: Option<usize>,
+}
+
+impl RollingFileConfig {
+    /// Creates a new `RollingFileConfig` for the file at `path`, rotated daily and keeping the
+    /// rotated files of the last week.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            rotation: LogRotation::Daily,
+            max_files: Some(7),
+        }
+    }
+
+    /// Sets when the file is rotated.
+    pub fn with_rotation(mut self, rotation: LogRotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Sets the number of rotated files to keep.
+    pub fn with_max_files(mut self, max_files: Option<usize>) -> Self {
+        self.max_files = max_files;
+        self
+    }
+}
+
+/// A [`MakeWriter`] appending to a log file rotated according to a [`RollingFileConfig`].
+///
+/// Clones write to the same file. Errors while rotating are reported to stderr, and the lines keep
+/// being appended to the current file until the rotation is retried a minute later.
+#[derive(Debug, Clone)]
+pub struct RollingFileWriter {
+    state: Arc<Mutex<RollingFile>>,
+}
+
+#[derive(Debug)]
+struct RollingFile {
+    config: RollingFileConfig,
+    file: File,
+    size: u64,
+    /// UTC time of the first line of the current file
+    opened_at: DateTime<Utc>,
+    next_rotation: Option<DateTime<Utc>>,
+    /// No rotation is attempted before this time, after a failed rotation
+    retry_rotation_at: Option<DateTime<Utc>>,
+}
+
+impl RollingFileWriter {
+    /// Opens the log file, appending to it if it exists.
+    pub fn new(config: RollingFileConfig) -> Result<Self, TraceError> {
+        let file = RollingFile::open(config, Utc::now())
+            .map_err(|error| TraceError::from(format!("failed to open the log file: {error}")))?;
+        Ok(Self {
+            state: Arc::new(Mutex::new(file)),
+        })
+    }
+}
+
+impl<'a> MakeWriter<'a> for RollingFileWriter {
+    type Writer = RollingFileGuard<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RollingFileGuard(self.state.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Writer holding the lock on the log file, returned by [`RollingFileWriter::make_writer`].
+#[derive(Debug)]
+pub struct RollingFileGuard<'a>(MutexGuard<'a, RollingFile>);
+
+impl Write for RollingFileGuard<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_at(buf, Utc::now())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.file.flush()
+    }
+}
+
+impl RollingFile {
+    fn open(config: RollingFileConfig, now: DateTime<Utc>) -> io::Result<Self> {
+        if let Some(directory) = config
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            fs::create_dir_all(directory)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let metadata = file.metadata()?;
+        // An existing file was started when it was last rotated, approximately at its creation.
+        let opened_at = metadata
+            .created()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or(now)
+            .min(now);
+        Ok(Self {
+            next_rotation: config.rotation.next_rotation(opened_at),
+            retry_rotation_at: None,
+            size: metadata.len(),
+            opened_at,
+            config,
+            file,
+        })
+    }
+
+    fn write_at(&mut self, buf: &[u8], now: DateTime<Utc>) -> io::Result<usize> {
+        if self.should_rotate(buf.len() as u64, now) {
+            if let Err(error) = self.rotate(now) {
+                // The subscriber cannot log its own errors. The rotation is retried later rather
+                // than on every line.
+                eprintln!("failed to rotate the log file: {error}");
+                self.retry_rotation_at = Some(now + ROTATION_RETRY_DELAY);
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn should_rotate(&self, length: u64, now: DateTime<Utc>) -> bool {
+        if self
+            .retry_rotation_at
+            .is_some_and(|retry_at| now < retry_at)
+        {
+            return false;
+        }
+        match self.config.rotation {
+            LogRotation::Size(max_size) => self.size > 0 && self.size + length > max_size,
+            _ => self.next_rotation.is_some_and(|next| now >= next),
+        }
+    }
+
+    fn rotate(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.config.path, self.rotated_path())?;
+        *self = Self::open(self.config.clone(), now)?;
+        // The new file may have kept the creation time of the renamed one on some file systems.
+        self.opened_at = now;
+        self.next_rotation = self.config.rotation.next_rotation(now);
+        self.delete_old_files()
+    }
+
+    /// The path the current file is renamed to, after the time of its first line.
+    fn rotated_path(&self) -> PathBuf {
+        let base = format!(
+            "{}.{}",
+            self.config.path.display(),
+            self.opened_at.format(ROTATED_SUFFIX_FORMAT)
+        );
+        // Files rotated by size within the same second are numbered.
+        let mut path = PathBuf::from(&base);
+        let mut index = 1;
+        while path.exists() {
+            path = PathBuf::from(format!("{base}.{index}"));
+            index += 1;
+        }
+        path
+    }
+
+    /// Deletes the oldest rotated files beyond `max_files`.
+    fn delete_old_files(&self) -> io::Result<()> {
+        let Some(max_files) = self.config.max_files else {
+            return Ok(());
+        };
+        let mut rotated = rotated_files(&self.config.path)?;
+        if rotated.len() <= max_files {
+            return Ok(());
+        }
+        rotated.sort_by_cached_key(|path| rotated_order(&self.config.path, path));
+        for path in &rotated[..rotated.len() - max_files] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rotated files of the log file at `path`, i.e. the files named after it with a suffix.
+fn rotated_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{file_name}.");
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    let mut rotated = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let is_rotated = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .is_some_and(|suffix| suffix.starts_with(|c: char| c.is_ascii_digit()));
+        if is_rotated {
+            rotated.push(entry.path());
+        }
+    }
+    Ok(rotated)
+}
+
+/// The rotation time and index of a file rotated from the log file at `path`, so that e.g.
+/// `auth.log.20241018T140000.10` comes after `auth.log.20241018T140000.2`.
+fn rotated_order(path: &Path, rotated: &Path) -> (String, u64) {
+    let suffix = rotated
+        .file_name()
+        .and_then(|name| name.to_str())
+        .zip(path.file_name().and_then(|name| name.to_str()))
+        .and_then(|(name, file_name)| name.strip_prefix(file_name))
+        .and_then(|suffix| suffix.strip_prefix('.'))
+        .unwrap_or_default();
+    match suffix.split_once('.') {
+        Some((time, index)) => (time.to_string(), index.parse().unwrap_or(0)),
+        None => (suffix.to_string(), 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("tracing-ext-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn test_parse_rotation() {
+        assert_eq!("daily".parse::<LogRotation>().unwrap(), LogRotation::Daily);
+        assert_eq!(
+            "100MB".parse::<LogRotation>().unwrap(),
+            LogRotation::Size(100 * 1024 * 1024)
+        );
+        assert_eq!(
+            "512".parse::<LogRotation>().unwrap(),
+            LogRotation::Size(512)
+        );
+        assert!("weekly".parse::<LogRotation>().is_err());
+        assert!("0MB".parse::<LogRotation>().is_err());
+    }
+
+    #[test]
+    fn test_size_rotation_and_retention() {
+        let directory = test_directory("size-rotation");
+        let config = RollingFileConfig::new(directory.join("auth.log"))
+            .with_rotation(LogRotation::Size(10))
+            .with_max_files(Some(2));
+        let mut file = RollingFile::open(config, Utc::now()).unwrap();
+
+        for line in ["line 1\n", "line 2\n", "line 3\n", "line 4\n"] {
+            file.write_at(line.as_bytes(), Utc::now()).unwrap();
+        }
+
+        let mut rotated = rotated_files(&directory.join("auth.log")).unwrap();
+        rotated.sort();
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(fs::read_to_string(&rotated[0]).unwrap(), "line 2\n");
+        assert_eq!(fs::read_to_string(&rotated[1]).unwrap(), "line 3\n");
+        assert_eq!(
+            fs::read_to_string(directory.join("auth.log")).unwrap(),
+            "line 4\n"
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_time_rotation() {
+        let directory = test_directory("time-rotation");
+        let config =
+            RollingFileConfig::new(directory.join("auth.log")).with_rotation(LogRotation::Hourly);
+        let start = Utc.with_ymd_and_hms(2024, 10, 18, 14, 30, 0).unwrap();
+        let mut file = RollingFile::open(config, start).unwrap();
+        file.opened_at = start;
+        file.next_rotation = LogRotation::Hourly.next_rotation(start);
+
+        file.write_at(b"before\n", start).unwrap();
+        file.write_at(b"after\n", start + Duration::hours(1))
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(directory.join("auth.log.20241018T143000")).unwrap(),
+            "before\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.join("auth.log")).unwrap(),
+            "after\n"
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_retention_orders_numbered_files() {
+        let directory = test_directory("numbered-retention");
+        let config = RollingFileConfig::new(directory.join("auth.log")).with_max_files(Some(2));
+        let file = RollingFile::open(config, Utc::now()).unwrap();
+        // Sorted as strings, `.10` would come before `.2` and be deleted.
+        for suffix in ["", ".1", ".2", ".9", ".10"] {
+            fs::write(
+                directory.join(format!("auth.log.20241018T140000{suffix}")),
+                "",
+            )
+            .unwrap();
+        }
+
+        file.delete_old_files().unwrap();
+
+        let mut kept: Vec<_> = rotated_files(&directory.join("auth.log"))
+            .unwrap()
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        kept.sort();
+        assert_eq!(
+            kept,
+            ["auth.log.20241018T140000.10", "auth.log.20241018T140000.9"]
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_failed_size_rotation_is_retried_later() {
+        let directory = test_directory("failed-rotation");
+        let path = directory.join("auth.log");
+        let config = RollingFileConfig::new(&path).with_rotation(LogRotation::Size(10));
+        let now = Utc::now();
+        let mut file = RollingFile::open(config, now).unwrap();
+
+        file.write_at(b"line 1\n", now).unwrap();
+        // The rotation fails, as the file to rename is gone.
+        fs::remove_file(&path).unwrap();
+        file.write_at(b"line 2\n", now).unwrap();
+        assert!(rotated_files(&path).unwrap().is_empty());
+
+        // The rotation would succeed now, but is not retried before the delay.
+        fs::write(&path, "").unwrap();
+        file.write_at(b"line 3\n", now).unwrap();
+        assert!(rotated_files(&path).unwrap().is_empty());
+
+        file.write_at(b"line 4\n", now + ROTATION_RETRY_DELAY)
+            .unwrap();
+        assert_eq!(rotated_files(&path).unwrap().len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 4\n");
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+//! Tail-based sampling for finished traces.
+//!
+//! Head sampling has to decide whether to record a trace before anything about it is known. The
+//! [`TailSamplingSpanProcessor`] instead buffers the spans of every trace until its local root
+//! spans end, and only then decides whether the whole trace is forwarded to the wrapped
+//! processor. A trace has several local roots when several requests share the trace of their
+//! caller, e.g. retried webhook calls. A trace is kept when:
+//! - any of its spans has an error status, or
+//! - any of its local root spans took at least [`TailSamplingConfig::latency_threshold`], or
+//! - the trace ID falls within [`TailSamplingConfig::ratio`].
+//!
+//! A trace whose local root spans don't all end within [`TailSamplingConfig::max_trace_duration`]
+//! is evicted from the buffer, and only forwarded when one of its spans has an error status.
+//!
+//! # Example:
+//! ```
+//! use opentelemetry_sdk::trace::{SimpleSpanProcessor, TracerProvider};
+//! use tracing_ext::{TailSamplingConfig, TailSamplingSpanProcessor};
+//!
+//! let exporter = opentelemetry_stdout::SpanExporter::default();
+//! let processor = SimpleSpanProcessor::new(Box::new(exporter));
+//! let provider = TracerProvider::builder()
+//!     .with_span_processor(TailSamplingSpanProcessor::new(processor, TailSamplingConfig::default()))
+//!     .build();
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use opentelemetry::trace::{Span, SpanId, Status, TraceContextExt, TraceId, TraceResult};
+use opentelemetry::Context;
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::trace::SpanProcessor;
+use opentelemetry_sdk::Resource;
+
+/// Configuration of the [`TailSamplingSpanProcessor`] decision.
+#[derive(Debug, Clone, Copy)]
+pub struct TailSamplingConfig {
+    /// Traces whose local root span lasts at least this long are always kept.
+    pub latency_threshold: Duration,
+    /// Fraction (`0.0..=1.0`) of the remaining healthy traces that are kept.
+    pub ratio: f64,
+    /// Maximum number of traces buffered at once. Spans of traces started while the buffer is full
+    /// are not buffered, and are only forwarded when they have an error status.
+    pub max_traces: usize,
+    /// Maximum time a trace is buffered waiting for its local root spans to end, after which it is
+    /// evicted.
+    pub max_trace_duration: Duration,
+}
+
+impl Default for TailSamplingConfig {
+    fn default() -> Self {
+        Self {
+            latency_threshold: Duration::from_millis(500),
+            ratio: 0.1,
+            max_traces: 10_000,
+            max_trace_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Spans of a trace collected until its local root spans end.
+#[derive(Debug)]
+struct TraceBuffer {
+    /// Local root spans started in the trace
+    root_span_ids: HashSet<SpanId>,
+    /// Local root spans not ended yet
+    open_root_span_ids: HashSet<SpanId>,
+    started_at: Instant,
+    spans: Vec<SpanData>,
+}
+
+/// The buffered traces, with their eviction deadlines in the order they were buffered.
+#[derive(Debug, Default)]
+struct TraceBuffers {
+    traces: HashMap<TraceId, TraceBuffer>,
+    /// Start time of the buffered traces, the oldest first. Traces that were already decided are
+    /// skipped when their deadline is reached.
+    deadlines: VecDeque<(Instant, TraceId)>,
+}
+
+/// A span processor that decides which traces to forward to the `inner` processor once their
+/// local root span has ended.
+///
+/// A local root is a span of a trace started in this process without a local parent, i.e. without
+/// parent or with a remote parent extracted from the request headers. Spans ending after all the
+/// local roots of their trace (e.g. detached background work) are only forwarded when they have an
+/// error status.
+#[derive(Debug)]
+pub struct TailSamplingSpanProcessor<P> {
+    inner: P,
+    config: TailSamplingConfig,
+    buffers: Mutex<TraceBuffers>,
+}
+
+impl<P: SpanProcessor> TailSamplingSpanProcessor<P> {
+    /// Creates a new `TailSamplingSpanProcessor` forwarding the kept traces to `inner`.
+    pub fn new(inner: P, config: TailSamplingConfig) -> Self {
+        Self {
+            inner,
+            config,
+            buffers: Mutex::new(TraceBuffers::default()),
+        }
+    }
+
+    fn should_keep(&self, trace_id: TraceId, buffer: &TraceBuffer) -> bool {
+        let has_error = buffer.spans.iter().any(is_error);
+        let is_slow = buffer
+            .spans
+            .iter()
+            .filter(|span| buffer.root_span_ids.contains(&span.span_context.span_id()))
+            .filter_map(|root| root.end_time.duration_since(root.start_time).ok())
+            .any(|duration| duration >= self.config.latency_threshold);
+        has_error || is_slow || is_in_ratio(trace_id, self.config.ratio)
+    }
+
+    /// Removes the traces buffered for longer than the maximum trace duration, forwarding the
+    /// spans of those with an error.
+    fn evict_expired(&self, buffers: &mut TraceBuffers, now: Instant) {
+        while let Some(&(started_at, trace_id)) = buffers.deadlines.front() {
+            if now.duration_since(started_at) < self.config.max_trace_duration {
+                break;
+            }
+            buffers.deadlines.pop_front();
+            // The trace may have been decided, and buffered again since.
+            let is_expired = buffers
+                .traces
+                .get(&trace_id)
+                .is_some_and(|buffer| buffer.started_at == started_at);
+            if !is_expired {
+                continue;
+            }
+            let Some(buffer) = buffers.traces.remove(&trace_id) else {
+                continue;
+            };
+            if buffer.spans.iter().any(is_error) {
+                for span in buffer.spans {
+                    self.inner.on_end(span);
+                }
+            }
+        }
+    }
+}
+
+fn is_error(span: &SpanData) -> bool {
+    matches!(span.status, Status::Error { .. })
+}
+
+/// Deterministic ratio lottery on the lower 64 bits of the trace ID, the same way the SDK's
+/// `TraceIdRatioBased` sampler decides.
+fn is_in_ratio(trace_id: TraceId, ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+    if ratio <= 0.0 {
+        return false;
+    }
+    let bytes = trace_id.to_bytes();
+    let (_, low) = bytes.split_at(8);
+    let trace_id_low = u64::from_be_bytes(low.try_into().unwrap_or_default());
+    let rnd_from_trace_id = trace_id_low >> 1;
+    let prob_upper_bound = (ratio * (1u64 << 63) as f64) as u64;
+    rnd_from_trace_id < prob_upper_bound
+}
+
+impl<P: SpanProcessor> SpanProcessor for TailSamplingSpanProcessor<P> {
+    fn on_start(&self, span: &mut opentelemetry_sdk::trace::Span, cx: &Context) {
+        let parent = cx.span();
+        let parent_context = parent.span_context();
+        let is_local_root = !parent_context.is_valid() || parent_context.is_remote();
+        if is_local_root {
+            let span_context = span.span_context();
+            let (trace_id, span_id) = (span_context.trace_id(), span_context.span_id());
+            let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+            // Taken under the lock, so that the deadlines are ordered.
+            let now = Instant::now();
+            self.evict_expired(&mut buffers, now);
+            if !buffers.traces.contains_key(&trace_id)
+                && buffers.traces.len() < self.config.max_traces
+            {
+                buffers.deadlines.push_back((now, trace_id));
+                buffers.traces.insert(
+                    trace_id,
+                    TraceBuffer {
+                        root_span_ids: HashSet::new(),
+                        open_root_span_ids: HashSet::new(),
+                        started_at: now,
+                        spans: Vec::new(),
+                    },
+                );
+            }
+            if let Some(buffer) = buffers.traces.get_mut(&trace_id) {
+                buffer.root_span_ids.insert(span_id);
+                buffer.open_root_span_ids.insert(span_id);
+            }
+        }
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let trace_id = span.span_context.trace_id();
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(buffer) = buffers.traces.get_mut(&trace_id) else {
+            drop(buffers);
+            if is_error(&span) {
+                self.inner.on_end(span);
+            }
+            return;
+        };
+
+        let span_id = span.span_context.span_id();
+        buffer.spans.push(span);
+        // The trace is decided once its last open local root ends.
+        if !buffer.open_root_span_ids.remove(&span_id) || !buffer.open_root_span_ids.is_empty() {
+            return;
+        }
+
+        let Some(buffer) = buffers.traces.remove(&trace_id) else {
+            return;
+        };
+        drop(buffers);
+        if self.should_keep(trace_id, &buffer) {
+            for span in buffer.spans {
+                self.inner.on_end(span);
+            }
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&self) -> TraceResult<()> {
+        // Traces whose root spans have not ended are incomplete and are dropped.
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        buffers.traces.clear();
+        buffers.deadlines.clear();
+        drop(buffers);
+        self.inner.shutdown()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::trace::{
+        Span, SpanContext, Status, TraceContextExt, TraceFlags, TraceState, Tracer,
+        TracerProvider as _,
+    };
+    use opentelemetry_sdk::trace::TracerProvider;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanProcessor for Collector {
+        fn on_start(&self, _span: &mut opentelemetry_sdk::trace::Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            self.0.lock().unwrap().push(span);
+        }
+
+        fn force_flush(&self) -> TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self) -> TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    fn provider(config: TailSamplingConfig) -> (TracerProvider, Collector) {
+        let collector = Collector::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(TailSamplingSpanProcessor::new(collector.clone(), config))
+            .build();
+        (provider, collector)
+    }
+
+    fn never_by_ratio() -> TailSamplingConfig {
+        TailSamplingConfig {
+            latency_threshold: Duration::from_secs(3600),
+            ratio: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_keeps_whole_trace_with_error() {
+        let (provider, collector) = provider(never_by_ratio());
+        let tracer = provider.tracer("test");
+        tracer.in_span("root", |cx| {
+            let mut child = tracer.start_with_context("child", &cx);
+            child.set_status(Status::error("denied"));
+            child.end();
+        });
+        let names: Vec<_> = collector
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| s.name.clone())
+            .collect();
+        assert_eq!(names, vec!["child", "root"]);
+    }
+
+    #[test]
+    fn test_drops_healthy_fast_trace() {
+        let (provider, collector) = provider(never_by_ratio());
+        let tracer = provider.tracer("test");
+        tracer.in_span("root", |cx| {
+            tracer.start_with_context("child", &cx).end();
+        });
+        assert!(collector.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_keeps_slow_trace() {
+        let (provider, collector) = provider(TailSamplingConfig {
+            latency_threshold: Duration::ZERO,
+            ratio: 0.0,
+            ..Default::default()
+        });
+        let tracer = provider.tracer("test");
+        tracer.in_span("root", |cx| cx.span().add_event("work", vec![]));
+        assert_eq!(collector.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_evicts_trace_whose_root_never_ends() {
+        let (provider, collector) = provider(TailSamplingConfig {
+            max_trace_duration: Duration::ZERO,
+            ..never_by_ratio()
+        });
+        let tracer = provider.tracer("test");
+        let root = tracer.start("stuck root");
+        let cx = Context::current_with_span(root);
+        let mut child = tracer.start_with_context("child", &cx);
+        child.set_status(Status::error("denied"));
+        child.end();
+
+        // Starting another trace evicts the stuck one, forwarding its error.
+        tracer.in_span("next root", |_| {});
+        let names: Vec<_> = collector
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| s.name.clone())
+            .collect();
+        assert_eq!(names, vec!["child"]);
+        drop(cx);
+    }
+
+    #[test]
+    fn test_keeps_concurrent_local_roots_of_one_trace() {
+        let (provider, collector) = provider(never_by_ratio());
+        let tracer = provider.tracer("test");
+        // Two requests sharing the trace of their caller.
+        let remote = SpanContext::new(
+            TraceId::from(1),
+            SpanId::from(1),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let cx = Context::new().with_remote_span_context(remote);
+        let mut first = tracer.start_with_context("first", &cx);
+        let second = tracer.start_with_context("second", &cx);
+        let second_cx = cx.with_span(second);
+
+        first.end();
+        assert!(collector.0.lock().unwrap().is_empty());
+
+        let mut child = tracer.start_with_context("child", &second_cx);
+        child.set_status(Status::error("denied"));
+        child.end();
+        second_cx.span().end();
+
+        let names: Vec<_> = collector
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| s.name.clone())
+            .collect();
+        assert_eq!(names, vec!["first", "child", "second"]);
+    }
+
+    #[test]
+    fn test_ratio_bounds() {
+        let trace_id = TraceId::from(u128::MAX);
+        assert!(is_in_ratio(trace_id, 1.0));
+        assert!(!is_in_ratio(trace_id, 0.0));
+        assert!(is_in_ratio(TraceId::from(1), 0.5));
+        assert!(!is_in_ratio(trace_id, 0.5));
+    }
+}
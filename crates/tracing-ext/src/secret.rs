@@ -0,0 +1,176 @@
+//! Values kept out of the logs.
+//!
+//! [`Secret`] wraps a credential so that its `Debug` and `Display` output is masked, including in the
+//! fields recorded by `#[tracing::instrument]`. [`HeaderRedactor`] masks the values of the sensitive
+//! headers, e.g. `Authorization` and `Cookie`, when logging a set of headers.
+//!
+//! # Example:
+//! ```
+//! use std::collections::HashMap;
+//!
+//! use tracing_ext::{HeaderRedactor, Secret};
+//!
+//! let token = Secret::new("s3cr3t".to_string());
+//! assert_eq!(format!("{token:?}"), "[REDACTED]");
+//!
+//! let headers = HashMap::from([("Authorization".to_string(), "Bearer s3cr3t".to_string())]);
+//! tracing::debug!(headers = ?HeaderRedactor::default().redact(&headers), "receiving request");
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// What masked values are replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Headers carrying credentials, masked by [`HeaderRedactor::default`].
+pub const DEFAULT_SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "x-hasura-admin-secret",
+];
+
+/// A value whose `Debug` and `Display` output is masked.
+///
+/// The value is only reachable through [`Secret::expose_secret`], which makes every use of the
+/// credential explicit.
+#[derive(Clone, Default)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    /// Wraps the `value`.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// The wrapped value.
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+
+    /// Unwraps the value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Masks the values of the sensitive headers when logging headers.
+#[derive(Debug, Clone)]
+pub struct HeaderRedactor {
+    /// Lowercase names of the sensitive headers
+    sensitive_headers: Vec<String>,
+}
+
+impl Default for HeaderRedactor {
+    fn default() -> Self {
+        Self {
+            sensitive_headers: DEFAULT_SENSITIVE_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl HeaderRedactor {
+    /// Masks the given headers in addition to the [`DEFAULT_SENSITIVE_HEADERS`].
+    pub fn with_sensitive_headers(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.sensitive_headers
+            .extend(names.into_iter().map(|name| name.to_ascii_lowercase()));
+        self
+    }
+
+    /// Whether the value of the header `name` is masked, ignoring case.
+    pub fn is_sensitive(&self, name: &str) -> bool {
+        self.sensitive_headers
+            .iter()
+            .any(|sensitive| sensitive.eq_ignore_ascii_case(name))
+    }
+
+    /// The `headers` with the values of the sensitive ones masked when formatted with `Debug`.
+    pub fn redact<'a>(&'a self, headers: &'a HashMap<String, String>) -> RedactedHeaders<'a> {
+        RedactedHeaders {
+            redactor: self,
+            headers,
+        }
+    }
+}
+
+/// Headers formatted with the values of the sensitive ones masked, see [`HeaderRedactor::redact`].
+pub struct RedactedHeaders<'a> {
+    redactor: &'a HeaderRedactor,
+    headers: &'a HashMap<String, String>,
+}
+
+impl fmt::Debug for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Sorted, so that the log lines are stable.
+        let headers: BTreeMap<_, _> = self
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.redactor.is_sensitive(name) {
+                    REDACTED
+                } else {
+                    value.as_str()
+                };
+                (name.as_str(), value)
+            })
+            .collect();
+        f.debug_map().entries(headers).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_is_masked() {
+        let secret = Secret::new("s3cr3t".to_string());
+        assert_eq!(format!("{secret:?}"), REDACTED);
+        assert_eq!(format!("{secret}"), REDACTED);
+        assert_eq!(format!("{:?}", Some(&secret)), "Some([REDACTED])");
+        assert_eq!(secret.expose_secret(), "s3cr3t");
+    }
+
+    #[test]
+    fn test_redact_headers() {
+        let headers = HashMap::from([
+            ("Authorization".to_string(), "Bearer s3cr3t".to_string()),
+            ("cookie".to_string(), "session=abc".to_string()),
+            ("x-tenant-token".to_string(), "t0k3n".to_string()),
+            ("content-type".to_string(), "application/json".to_string()),
+        ]);
+        let redactor =
+            HeaderRedactor::default().with_sensitive_headers(["X-Tenant-Token".to_string()]);
+
+        let output = format!("{:?}", redactor.redact(&headers));
+        assert_eq!(
+            output,
+            "{\"Authorization\": \"[REDACTED]\", \"content-type\": \"application/json\", \
+             \"cookie\": \"[REDACTED]\", \"x-tenant-token\": \"[REDACTED]\"}"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Spawning of background tasks without losing the trace.
+//!
+//! A future spawned with `tokio::spawn` runs outside the OpenTelemetry context of its caller, so
+//! its spans start new unrelated traces and the caller's baggage is lost. Background work such as
+//! cache refreshes or audit flushing should instead be spawned with:
+//! - [`spawn_in_current_context`], to continue the trace of the caller, e.g. work the request
+//!   waits for or that belongs to it,
+//! - [`spawn_linked_trace`], to start a new trace linked to the caller, e.g. detached follow-up
+//!   work outliving the request.
+//!
+//! # Example:
+//! ```no_run
+//! use tracing_ext::{spawn_in_current_context, spawn_linked_trace, SpanVisibility, Successful};
+//!
+//! # async fn example() {
+//! let refresh = spawn_in_current_context(async { /* refresh the cache */ });
+//! refresh.await.unwrap();
+//!
+//! spawn_linked_trace("flush audit log", "Flush audit log", SpanVisibility::Internal, || {
+//!     Box::pin(async { Successful::new(()) })
+//! });
+//! # }
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+
+use opentelemetry::trace::FutureExt;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use crate::traceable::Traceable;
+use crate::tracer::{global_tracer, AttributeValue, SpanLink, SpanVisibility};
+
+/// Spawns `future` on the Tokio runtime in the current OpenTelemetry context, with its active span
+/// and baggage, and in the current `tracing` span.
+pub fn spawn_in_current_context<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future.with_current_context().in_current_span())
+}
+
+/// Spawns the future returned by `f` on the Tokio runtime, in a span named `name` opening a new
+/// trace linked to the current span, with the current baggage. See
+/// [`Tracer::new_trace_async_with_link`](crate::tracer::Tracer::new_trace_async_with_link).
+pub fn spawn_linked_trace<R, F>(
+    name: &'static str,
+    display_name: impl Into<AttributeValue> + Send + 'static,
+    visibility: SpanVisibility,
+    f: F,
+) -> JoinHandle<R>
+where
+    F: FnOnce() -> Pin<Box<dyn Future<Output = R> + Send + 'static>> + Send + 'static,
+    R: Traceable + Send + 'static,
+{
+    let link = SpanLink::from_current_span();
+    tokio::spawn(
+        async move {
+            global_tracer()
+                .new_trace_async_with_link(name, display_name, visibility, link, f)
+                .await
+        }
+        .in_current_span(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::trace::{Span as _, TraceContextExt, Tracer as _, TracerProvider as _};
+    use opentelemetry::{Context, KeyValue};
+
+    use super::*;
+    use crate::testing::InMemorySpanExporter;
+    use crate::Successful;
+
+    /// The value of the baggage entry `key` in the current context.
+    fn current_baggage(key: &str) -> Option<String> {
+        Context::current()
+            .baggage()
+            .get(key)
+            .map(|value| value.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_spawn_in_current_context() {
+        let exporter = InMemorySpanExporter::new();
+        let tracer = exporter.tracer_provider().tracer("test");
+        let span = tracer.start("request");
+        let span_context = span.span_context().clone();
+        let context =
+            Context::current_with_span(span).with_baggage(vec![KeyValue::new("tenant.id", "acme")]);
+
+        let handle = {
+            let _guard = context.attach();
+            spawn_in_current_context(async {
+                (
+                    Context::current().span().span_context().clone(),
+                    current_baggage("tenant.id"),
+                )
+            })
+        };
+        let (active_span, tenant) = handle.await.unwrap();
+
+        assert_eq!(active_span, span_context);
+        assert_eq!(tenant.as_deref(), Some("acme"));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_linked_trace() {
+        let exporter = InMemorySpanExporter::global();
+        let span = exporter.tracer_provider().tracer("test").start("request");
+        let span_context = span.span_context().clone();
+        let context =
+            Context::current_with_span(span).with_baggage(vec![KeyValue::new("tenant.id", "acme")]);
+
+        let handle = {
+            let _guard = context.attach();
+            spawn_linked_trace(
+                "refresh jwks",
+                "Refresh JWKS",
+                SpanVisibility::Internal,
+                || Box::pin(async { Successful::new(current_baggage("tenant.id")) }),
+            )
+        };
+        let tenant = handle.await.unwrap().into_inner();
+
+        assert_eq!(tenant.as_deref(), Some("acme"));
+        exporter
+            .assert_span("refresh jwks")
+            .is_root()
+            .has_link_to(&span_context)
+            .has_attribute("internal.visibility", "internal");
+    }
+}
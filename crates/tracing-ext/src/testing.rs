@@ -0,0 +1,362 @@
+//! Test support for asserting on the spans produced by the code under test.
+//!
+//! Requires the `test-support` feature. [`InMemorySpanExporter`] keeps the finished spans in
+//! memory, and [`SpanAssertion`] provides chainable assertions on them. [`CapturedLines`] keeps
+//! the formatted log lines in memory.
+//!
+//! # Example:
+//! ```
+//! use tracing_ext::testing::InMemorySpanExporter;
+//! use tracing_ext::{Successful, SpanVisibility};
+//!
+//! let exporter = InMemorySpanExporter::new();
+//! exporter
+//!     .tracer()
+//!     .in_span("request", "request", SpanVisibility::User, || Successful::new(()));
+//!
+//! exporter
+//!     .assert_span("request")
+//!     .has_attribute("internal.visibility", "user")
+//!     .is_ok();
+//! ```
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::trace::{SpanContext, SpanKind, Status, TraceId, TracerProvider as _};
+use opentelemetry::Value;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::TracerProvider;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::otlp::{BaggageAttributesConfig, BaggageSpanProcessor};
+use crate::tracer::{Tracer, GLOBAL_TRACER_NAME};
+
+/// A span exporter keeping the exported spans in memory.
+///
+/// Clones share the same spans, so a clone can be installed in a tracer provider while the original
+/// is used for the assertions.
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySpanExporter {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl InMemorySpanExporter {
+    /// Creates a new `InMemorySpanExporter` without spans.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a tracer provider exporting to this exporter as soon as spans end, with the baggage
+    /// processing of a default [`crate::TracingConfig`], which copies no baggage entry.
+    pub fn tracer_provider(&self) -> TracerProvider {
+        self.tracer_provider_with_baggage_attributes(BaggageAttributesConfig::default())
+    }
+
+    /// Builds a tracer provider exporting to this exporter as soon as spans end, copying the
+    /// baggage entries allowed by `baggage_attributes` like [`crate::init_tracing`] does.
+    pub fn tracer_provider_with_baggage_attributes(
+        &self,
+        baggage_attributes: BaggageAttributesConfig,
+    ) -> TracerProvider {
+        TracerProvider::builder()
+            .with_span_processor(BaggageSpanProcessor::new(baggage_attributes))
+            .with_simple_exporter(self.clone())
+            .build()
+    }
+
+    /// A tracer exporting to this exporter, without touching the global tracer provider.
+    pub fn tracer(&self) -> Tracer {
+        let tracer = self.tracer_provider().tracer(GLOBAL_TRACER_NAME);
+        Tracer::new(BoxedTracer::new(Box::new(tracer)))
+    }
+
+    /// Installs a tracer provider exporting to this exporter as the global one, for the code under
+    /// test using [`crate::global_tracer`].
+    ///
+    /// The global tracer provider is shared by all the tests of a binary, so tests asserting on
+    /// global spans should only look for the spans they produced.
+    pub fn install_global(&self) {
+        global::set_tracer_provider(self.tracer_provider());
+    }
+
+    /// An exporter installed once as the global one and shared by all the tests of a binary, so that
+    /// concurrent tests don't replace each other's global tracer provider.
+    ///
+    /// Tests should only look for the spans they produced, e.g. by giving them unique names.
+    pub fn global() -> Self {
+        static GLOBAL: OnceLock<InMemorySpanExporter> = OnceLock::new();
+        GLOBAL
+            .get_or_init(|| {
+                let exporter = Self::new();
+                exporter.install_global();
+                exporter
+            })
+            .clone()
+    }
+
+    /// The spans finished so far, in the order they ended.
+    pub fn finished_spans(&self) -> Vec<SpanData> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// Removes the spans finished so far.
+    pub fn reset(&self) {
+        self.spans.lock().unwrap().clear();
+    }
+
+    /// Returns the assertions on the last finished span named `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no span named `name` finished.
+    #[track_caller]
+    pub fn assert_span(&self, name: &str) -> SpanAssertion {
+        self.assert_span_matching(name, |_| true)
+    }
+
+    /// Returns the assertions on the last finished span named `name` in the trace `trace_id`, e.g.
+    /// to tell the global spans of a test from the ones of the concurrent tests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no span named `name` finished in the trace.
+    #[track_caller]
+    pub fn assert_span_in_trace(&self, name: &str, trace_id: TraceId) -> SpanAssertion {
+        self.assert_span_matching(name, |span| span.span_context.trace_id() == trace_id)
+    }
+
+    #[track_caller]
+    fn assert_span_matching(
+        &self,
+        name: &str,
+        matches: impl Fn(&SpanData) -> bool,
+    ) -> SpanAssertion {
+        let spans = self.finished_spans();
+        match spans
+            .iter()
+            .rev()
+            .find(|span| span.name == name && matches(span))
+        {
+            Some(span) => SpanAssertion { span: span.clone() },
+            None => panic!(
+                "no span named {name:?} finished, found: {:?}",
+                spans.iter().map(|span| &span.name).collect::<Vec<_>>()
+            ),
+        }
+    }
+}
+
+impl SpanExporter for InMemorySpanExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.spans.lock().unwrap().extend(batch);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Chainable assertions on a finished span.
+#[derive(Debug)]
+pub struct SpanAssertion {
+    span: SpanData,
+}
+
+impl SpanAssertion {
+    /// The asserted span.
+    pub fn span(&self) -> &SpanData {
+        &self.span
+    }
+
+    fn attribute(&self, key: &str) -> Option<&Value> {
+        self.span
+            .attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| &attribute.value)
+    }
+
+    /// Asserts that the span has the attribute `key` with the given `value`.
+    #[track_caller]
+    pub fn has_attribute(&self, key: &str, value: impl Into<Value>) -> &Self {
+        let value = value.into();
+        match self.attribute(key) {
+            Some(actual) => assert_eq!(
+                actual, &value,
+                "span {:?} has attribute {key:?} = {actual:?}, expected {value:?}",
+                self.span.name
+            ),
+            None => panic!(
+                "span {:?} has no attribute {key:?}, found: {:?}",
+                self.span.name, self.span.attributes
+            ),
+        }
+        self
+    }
+
+    /// Asserts that the span does not have the attribute `key`.
+    #[track_caller]
+    pub fn has_no_attribute(&self, key: &str) -> &Self {
+        if let Some(value) = self.attribute(key) {
+            panic!(
+                "span {:?} has attribute {key:?} = {value:?}",
+                self.span.name
+            );
+        }
+        self
+    }
+
+    /// Asserts that the span has an error status.
+    #[track_caller]
+    pub fn has_error(&self) -> &Self {
+        assert!(
+            matches!(self.span.status, Status::Error { .. }),
+            "span {:?} has status {:?}, expected an error",
+            self.span.name,
+            self.span.status
+        );
+        self
+    }
+
+    /// Asserts that the span has an error status with the given `description`.
+    #[track_caller]
+    pub fn has_error_description(&self, description: &str) -> &Self {
+        match &self.span.status {
+            Status::Error {
+                description: actual,
+            } => assert_eq!(
+                actual, description,
+                "span {:?} has error description {actual:?}, expected {description:?}",
+                self.span.name
+            ),
+            status => panic!(
+                "span {:?} has status {status:?}, expected an error",
+                self.span.name
+            ),
+        }
+        self
+    }
+
+    /// Asserts that the span does not have an error status.
+    #[track_caller]
+    pub fn is_ok(&self) -> &Self {
+        assert!(
+            !matches!(self.span.status, Status::Error { .. }),
+            "span {:?} has status {:?}, expected no error",
+            self.span.name,
+            self.span.status
+        );
+        self
+    }
+
+    /// Asserts that the span is of the given `kind`.
+    #[track_caller]
+    pub fn has_kind(&self, kind: SpanKind) -> &Self {
+        assert_eq!(
+            self.span.span_kind, kind,
+            "span {:?} has kind {:?}, expected {kind:?}",
+            self.span.name, self.span.span_kind
+        );
+        self
+    }
+
+    /// Asserts that the span has an event named `name`.
+    #[track_caller]
+    pub fn has_event(&self, name: &str) -> &Self {
+        assert!(
+            self.span.events.iter().any(|event| event.name == name),
+            "span {:?} has no event {name:?}, found: {:?}",
+            self.span.name,
+            self.span
+                .events
+                .iter()
+                .map(|event| &event.name)
+                .collect::<Vec<_>>()
+        );
+        self
+    }
+
+    /// Asserts that the span is a child of the span with the given context.
+    #[track_caller]
+    pub fn has_parent(&self, parent: &SpanContext) -> &Self {
+        assert!(
+            self.span.span_context.trace_id() == parent.trace_id()
+                && self.span.parent_span_id == parent.span_id(),
+            "span {:?} is not a child of {parent:?}",
+            self.span.name
+        );
+        self
+    }
+
+    /// Asserts that the span has no parent, i.e. starts a new trace.
+    #[track_caller]
+    pub fn is_root(&self) -> &Self {
+        assert_eq!(
+            self.span.parent_span_id,
+            opentelemetry::trace::SpanId::INVALID,
+            "span {:?} has a parent",
+            self.span.name
+        );
+        self
+    }
+
+    /// Asserts that the span is linked to the span with the given context.
+    #[track_caller]
+    pub fn has_link_to(&self, linked: &SpanContext) -> &Self {
+        assert!(
+            self.span
+                .links
+                .iter()
+                .any(|link| link.span_context.span_id() == linked.span_id()
+                    && link.span_context.trace_id() == linked.trace_id()),
+            "span {:?} is not linked to {linked:?}",
+            self.span.name
+        );
+        self
+    }
+}
+
+/// A log writer keeping the formatted lines in memory, to assert on the logs of the code under
+/// test.
+///
+/// Clones share the same lines, so a clone can be given to a `fmt` subscriber or layer with
+/// `with_writer` while the original is used for the assertions.
+#[derive(Debug, Clone, Default)]
+pub struct CapturedLines {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl CapturedLines {
+    /// Creates a new `CapturedLines` without lines.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The lines written so far.
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.lock().unwrap()).into_owned()
+    }
+}
+
+impl io::Write for CapturedLines {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLines {
+    type Writer = CapturedLines;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
@@ -1,19 +1,25 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
+use std::time::SystemTime;
 
 use http::HeaderMap;
 use opentelemetry::baggage::{BaggageExt, KeyValueMetadata};
 use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::propagation::TextMapPropagator;
 use opentelemetry::trace::{
-    get_active_span, FutureExt, Span, SpanContext, SpanRef, TraceContextExt, Tracer as OtelTracer,
+    get_active_span, FutureExt, Link, Span, SpanContext, SpanKind, SpanRef, TraceContextExt,
+    Tracer as OtelTracer,
 };
-use opentelemetry::{Context, Key};
+use opentelemetry::{Context, Key, KeyValue};
 use opentelemetry_http::HeaderExtractor;
+use opentelemetry_semantic_conventions as semcov;
 
 use crate::traceable::{ErrorVisibility, Traceable, TraceableError};
 pub static GLOBAL_TRACER_NAME: &str = "tracing-ext";
 
-#[derive(Clone, Copy, derive_more::Display)]
+#[derive(Debug, Clone, Copy, derive_more::Display)]
 pub enum SpanVisibility {
     #[display("internal")]
     Internal,
@@ -77,18 +83,47 @@ where
 
 pub type AttributeValue = opentelemetry::Value;
 
+/// Awaits `future`, then sets the display name of the active span and its attributes based on
+/// `visibility` and the result of the future.
+async fn traced_future<'a, R>(
+    display_name: impl Into<AttributeValue>,
+    visibility: SpanVisibility,
+    future: Pin<Box<dyn Future<Output = R> + 'a + Send>>,
+) -> R
+where
+    R: Traceable,
+{
+    let result = future.await;
+    get_active_span(|span| {
+        set_attribute_on_span(
+            &span,
+            AttributeVisibility::Default,
+            "display.name",
+            display_name,
+        );
+        set_span_attributes(&span, visibility, &result);
+    });
+    result
+}
+
+/// The `key` prefixed with `internal.` if `visibility` is `Internal`.
+fn key_with_visibility(visibility: AttributeVisibility, key: &'static str) -> Key {
+    match visibility {
+        AttributeVisibility::Default => key.into(),
+        AttributeVisibility::Internal => format!("internal.{key}").into(),
+    }
+}
+
 fn set_attribute_on_span(
     span: &SpanRef,
     visibility: AttributeVisibility,
     key: &'static str,
     value: impl Into<AttributeValue>,
 ) {
-    let key_with_visibility: Key = match visibility {
-        AttributeVisibility::Default => key.into(),
-        AttributeVisibility::Internal => format!("internal.{key}").into(),
-    };
-
-    span.set_attribute(opentelemetry::KeyValue::new(key_with_visibility, value));
+    span.set_attribute(opentelemetry::KeyValue::new(
+        key_with_visibility(visibility, key),
+        value,
+    ));
 }
 
 /// Sets an attribute on the active span, prefixing the `key` with `internal.` if `visibility` is `Internal`.
@@ -101,9 +136,103 @@ pub fn set_attribute_on_active_span(
 }
 
 /// Adds an event on the active span, with the given `name` and no attributes.
-// TODO: Add support for attributes
 pub fn add_event_on_active_span(name: String) {
-    get_active_span(|span| span.add_event(name, vec![]));
+    add_span_event_on_active_span(SpanEvent::new(name));
+}
+
+/// Adds the given `event` on the active span.
+pub fn add_span_event_on_active_span(event: SpanEvent) {
+    get_active_span(|span| match event.timestamp {
+        Some(timestamp) => span.add_event_with_timestamp(event.name, timestamp, event.attributes),
+        None => span.add_event(event.name, event.attributes),
+    });
+}
+
+/// Adds an `exception` event for the given `error` on the active span.
+pub fn record_exception_on_active_span<E: TraceableError + ?Sized>(error: &E) {
+    add_span_event_on_active_span(SpanEvent::exception(error));
+}
+
+/// An event on a span, i.e. a named point in time with attributes.
+///
+/// # Example:
+/// ```
+/// use tracing_ext::{add_span_event_on_active_span, AttributeVisibility, SpanEvent};
+///
+/// add_span_event_on_active_span(
+///     SpanEvent::new("kong responded")
+///         .with_attribute(AttributeVisibility::Default, "http.response.status_code", 200)
+///         .with_attribute(AttributeVisibility::Internal, "consumer.valid", true),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpanEvent {
+    name: Cow<'static, str>,
+    timestamp: Option<SystemTime>,
+    attributes: Vec<KeyValue>,
+}
+
+impl SpanEvent {
+    /// Creates a new `SpanEvent` with the given `name`, timestamped when it is added to the span.
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            name: name.into(),
+            timestamp: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Creates an `exception` event for the given `error`, following the `exception.*` semantic
+    /// conventions. Like the span status, the message of an internal error is only recorded in an
+    /// internal attribute.
+    pub fn exception<E: TraceableError + ?Sized>(error: &E) -> Self {
+        let event = Self::new("exception").with_attribute(
+            AttributeVisibility::Default,
+            semcov::trace::EXCEPTION_TYPE,
+            std::any::type_name::<E>(),
+        );
+        let event = match error.visibility() {
+            ErrorVisibility::User => event.with_attribute(
+                AttributeVisibility::Default,
+                semcov::trace::EXCEPTION_MESSAGE,
+                error.description(),
+            ),
+            ErrorVisibility::Internal => event
+                .with_attribute(
+                    AttributeVisibility::Default,
+                    semcov::trace::EXCEPTION_MESSAGE,
+                    "Internal error",
+                )
+                .with_attribute(
+                    AttributeVisibility::Internal,
+                    semcov::trace::EXCEPTION_MESSAGE,
+                    error.description(),
+                ),
+        };
+        event.with_attribute(
+            AttributeVisibility::Internal,
+            "exception.details",
+            error.details(),
+        )
+    }
+
+    /// Adds an attribute to the event, prefixing the `key` with `internal.` if `visibility` is `Internal`.
+    pub fn with_attribute(
+        mut self,
+        visibility: AttributeVisibility,
+        key: &'static str,
+        value: impl Into<AttributeValue>,
+    ) -> Self {
+        self.attributes
+            .push(KeyValue::new(key_with_visibility(visibility, key), value));
+        self
+    }
+
+    /// Sets the time at which the event occurred, instead of the time it is added to the span.
+    pub fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
 }
 
 /// Runs the given closure `f` in the current span by attaching the given `baggage` to the current context.
@@ -139,6 +268,43 @@ impl SpanLink {
             baggage_items,
         }
     }
+
+    /// Creates a new `SpanLink` from the propagation headers in `headers`, e.g. the headers of the
+    /// end-user request forwarded in a webhook payload. Header names are matched case-insensitively.
+    /// Returns `None` if the headers carry no valid span context for the installed propagators.
+    pub fn from_headers(headers: &HashMap<String, String>) -> Option<Self> {
+        global::get_text_map_propagator(|propagator| {
+            Self::from_headers_with_propagator(propagator, headers)
+        })
+    }
+
+    /// Like [`SpanLink::from_headers`], with the given `propagator` instead of the installed ones.
+    pub fn from_headers_with_propagator(
+        propagator: &dyn TextMapPropagator,
+        headers: &HashMap<String, String>,
+    ) -> Option<Self> {
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.clone()))
+            .collect();
+        let context = propagator.extract_with_context(&Context::new(), &headers);
+        let span_context = context.span().span_context().clone();
+        // The no-op propagator extracts the current context rather than a remote one.
+        if !span_context.is_valid() || !span_context.is_remote() {
+            return None;
+        }
+        let baggage_items = context
+            .baggage()
+            .into_iter()
+            .map(|(key, (value, metadata))| {
+                KeyValueMetadata::new(key.clone(), value.clone(), metadata.clone())
+            })
+            .collect();
+        Some(Self {
+            span_context,
+            baggage_items,
+        })
+    }
 }
 
 /// Wrapper around the OpenTelemetry tracer. Used for providing convenience methods to add spans.
@@ -183,7 +349,7 @@ impl Tracer {
     /// on the span based on `visibility` and sets the span's error attributes based on the result of the closure.
     /// The span is linked to the given `link`.
     pub async fn new_trace_async_with_link<'a, R, F>(
-        &'a self,
+        &self,
         name: &'static str,
         display_name: impl Into<AttributeValue>,
         visibility: SpanVisibility,
@@ -203,27 +369,15 @@ impl Tracer {
         let mut span = self.tracer.start_with_context(name, &context);
         // Link the span to the given span context.
         span.add_link(link.span_context, Vec::new());
-        async move {
-            let result = f().await;
-            get_active_span(|span_ref| {
-                set_attribute_on_span(
-                    &span_ref,
-                    AttributeVisibility::Default,
-                    "display.name",
-                    display_name,
-                );
-                set_span_attributes(&span_ref, visibility, &result);
-            });
-            result
-        }
-        .with_context(context.with_span(span)) // Run the above async block within the new span
-        .await
+        traced_future(display_name, visibility, f())
+            .with_context(context.with_span(span)) // Run the future within the new span
+            .await
     }
 
     /// Runs the given closure `f` asynchronously in a new span with the given `name`, and sets a visibility attribute
     /// on the span based on `visibility` and sets the span's error attributes based on the result of the closure.
     pub async fn in_span_async<'a, R, F>(
-        &'a self,
+        &self,
         name: &'static str,
         display_name: impl Into<AttributeValue>,
         visibility: SpanVisibility,
@@ -237,26 +391,70 @@ impl Tracer {
     {
         self.tracer
             .in_span(name, |cx| {
-                async move {
-                    let result = f().await;
-                    get_active_span(|span| {
-                        set_attribute_on_span(
-                            &span,
-                            AttributeVisibility::Default,
-                            "display.name",
-                            display_name,
-                        );
-                        set_span_attributes(&span, visibility, &result);
-                    });
-                    result
-                }
-                .with_context(cx)
+                traced_future(display_name, visibility, f()).with_context(cx)
             })
             .await
     }
 
+    /// Runs the given closure `f` asynchronously in a new span of the given `kind`, with the given `name`
+    /// and initial `attributes`, and sets a visibility attribute on the span based on `visibility` and sets
+    /// the span's error attributes based on the result of the closure.
+    pub async fn in_span_async_with_kind<'a, R, F>(
+        &self,
+        name: impl Into<Cow<'static, str>>,
+        display_name: impl Into<AttributeValue>,
+        visibility: SpanVisibility,
+        kind: SpanKind,
+        attributes: Vec<KeyValue>,
+        f: F,
+    ) -> R
+    where
+        F: FnOnce() -> Pin<Box<dyn Future<Output = R> + 'a + Send>>,
+        R: Traceable,
+    {
+        let span = self
+            .tracer
+            .span_builder(name)
+            .with_kind(kind)
+            .with_attributes(attributes)
+            .start(&self.tracer);
+        traced_future(display_name, visibility, f())
+            .with_context(Context::current_with_span(span))
+            .await
+    }
+
+    /// Runs the given closure `f` asynchronously in a new child span of the current span with the given
+    /// `name`, linked to the given `link` if any, and sets a visibility attribute on the span based on
+    /// `visibility` and sets the span's error attributes based on the result of the closure.
+    /// Unlike [`Tracer::new_trace_async_with_link`], the span stays in the current trace.
+    pub async fn in_span_async_with_link<'a, R, F>(
+        &self,
+        name: &'static str,
+        display_name: impl Into<AttributeValue>,
+        visibility: SpanVisibility,
+        link: Option<SpanLink>,
+        f: F,
+    ) -> R
+    where
+        F: FnOnce() -> Pin<Box<dyn Future<Output = R> + 'a + Send>>,
+        R: Traceable,
+    {
+        let links = link
+            .map(|link| Link::with_context(link.span_context))
+            .into_iter()
+            .collect();
+        let span = self
+            .tracer
+            .span_builder(name)
+            .with_links(links)
+            .start(&self.tracer);
+        traced_future(display_name, visibility, f())
+            .with_context(Context::current_with_span(span))
+            .await
+    }
+
     pub async fn in_span_async_with_parent_context<'a, R, F>(
-        &'a self,
+        &self,
         name: &'static str,
         display_name: impl Into<AttributeValue>,
         visibility: SpanVisibility,
@@ -281,3 +479,163 @@ impl Tracer {
 pub fn global_tracer() -> Tracer {
     Tracer::new(opentelemetry::global::tracer(GLOBAL_TRACER_NAME))
 }
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::noop::NoopTextMapPropagator;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+    use super::*;
+    use crate::testing::InMemorySpanExporter;
+    use crate::Successful;
+
+    #[test]
+    fn test_span_link_from_headers() {
+        let propagator = TraceContextPropagator::new();
+
+        let headers = HashMap::from([(
+            "Traceparent".to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        )]);
+        let link = SpanLink::from_headers_with_propagator(&propagator, &headers).unwrap();
+        assert_eq!(
+            link.span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert!(link.span_context.is_remote());
+
+        let headers = HashMap::from([("authorization".to_string(), "Bearer x".to_string())]);
+        assert!(SpanLink::from_headers_with_propagator(&propagator, &headers).is_none());
+
+        // The no-op propagator extracts the current span, which is not a span to link to.
+        let tracer = InMemorySpanExporter::new().tracer();
+        tracer.in_span("current", "current", SpanVisibility::Internal, || {
+            let propagator = NoopTextMapPropagator::new();
+            assert!(SpanLink::from_headers_with_propagator(&propagator, &headers).is_none());
+            Successful::new(())
+        });
+    }
+
+    #[crate::traced(name = "double")]
+    fn double(value: i32) -> crate::Successful<i32> {
+        crate::Successful::new(value * 2)
+    }
+
+    #[crate::traced(name = "connect_traced", visibility = "user")]
+    async fn connect(fail: bool) -> Result<&'static str, ConnectionError> {
+        if fail {
+            return Err(ConnectionError);
+        }
+        Ok("connected")
+    }
+
+    #[test]
+    fn test_span_visibility_and_error_attributes() {
+        let exporter = InMemorySpanExporter::new();
+        let tracer = exporter.tracer();
+
+        let _ = tracer.in_span("connect", "Connect", SpanVisibility::User, || {
+            Err::<(), _>(ConnectionError)
+        });
+        exporter
+            .assert_span("connect")
+            .has_attribute("display.name", "Connect")
+            .has_attribute("internal.visibility", "user")
+            .has_attribute("internal.error_description", "connection refused")
+            // Internal errors are hidden from the status of user-visible spans.
+            .has_error_description("Internal error");
+
+        let _ = tracer.in_span("connect", "Connect", SpanVisibility::Internal, || {
+            Err::<(), _>(ConnectionError)
+        });
+        exporter
+            .assert_span("connect")
+            .has_attribute("internal.visibility", "internal")
+            .has_error_description("connection refused");
+    }
+
+    #[tokio::test]
+    async fn test_new_trace_async_with_link() {
+        let exporter = InMemorySpanExporter::new();
+        let tracer = exporter.tracer();
+
+        let link = tracer.in_span("parent", "parent", SpanVisibility::User, || {
+            Successful::new(SpanLink::from_current_span())
+        });
+        let link = link.into_inner();
+        tracer
+            .new_trace_async_with_link(
+                "linked",
+                "linked",
+                SpanVisibility::User,
+                link.clone(),
+                || Box::pin(async { Successful::new(()) }),
+            )
+            .await;
+
+        exporter
+            .assert_span("linked")
+            .is_root()
+            .has_link_to(&link.span_context)
+            .is_ok();
+    }
+
+    #[tokio::test]
+    async fn test_traced() {
+        let exporter = InMemorySpanExporter::global();
+
+        assert_eq!(double(21).into_inner(), 42);
+        exporter
+            .assert_span("double")
+            .has_attribute("display.name", "double")
+            .has_attribute("internal.visibility", "internal")
+            .is_ok();
+
+        assert_eq!(connect(false).await.unwrap(), "connected");
+        exporter
+            .assert_span("connect_traced")
+            .has_attribute("internal.visibility", "user")
+            .is_ok();
+
+        assert!(connect(true).await.is_err());
+        exporter
+            .assert_span("connect_traced")
+            .has_attribute("internal.error_description", "connection refused")
+            // Internal errors are hidden from the status of user-visible spans.
+            .has_error_description("Internal error");
+    }
+
+    #[derive(Debug, derive_more::Display)]
+    #[display("connection refused")]
+    struct ConnectionError;
+
+    impl TraceableError for ConnectionError {
+        fn visibility(&self) -> ErrorVisibility {
+            ErrorVisibility::Internal
+        }
+    }
+
+    #[test]
+    fn test_exception_event_hides_internal_message() {
+        let event = SpanEvent::exception(&ConnectionError);
+        let attribute = |key: &str| {
+            event
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key.as_str() == key)
+                .map(|attribute| attribute.value.to_string())
+        };
+        assert_eq!(event.name, "exception");
+        assert!(attribute("exception.type")
+            .unwrap()
+            .ends_with("ConnectionError"));
+        assert_eq!(
+            attribute("exception.message").as_deref(),
+            Some("Internal error")
+        );
+        assert_eq!(
+            attribute("internal.exception.message").as_deref(),
+            Some("connection refused")
+        );
+    }
+}